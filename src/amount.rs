@@ -0,0 +1,183 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// Unsigned amount of a coin. It is a u128 by default; the `uint256` feature switches it to `Uint256` for tokens
+// bridged from EVM chains whose amounts do not fit in 128 bits. Both expose the same checked operations.
+#[cfg(not(feature = "uint256"))]
+pub type Amount = u128;
+#[cfg(feature = "uint256")]
+pub type Amount = crate::uint256::Uint256;
+
+#[cfg(not(feature = "uint256"))]
+pub const ZERO: Amount = 0;
+#[cfg(feature = "uint256")]
+pub const ZERO: Amount = crate::uint256::Uint256::ZERO;
+
+// (floor(a * b / c), a * b mod c) with a double-width intermediate, or None if c is zero or the quotient does not fit
+#[cfg(not(feature = "uint256"))]
+pub fn mul_div_rem(a: Amount, b: Amount, c: Amount) -> Option<(Amount, Amount)> {
+  crate::decimal::mul_div_rem(a, b, c)
+}
+#[cfg(feature = "uint256")]
+pub fn mul_div_rem(a: Amount, b: Amount, c: Amount) -> Option<(Amount, Amount)> {
+  a.mul_div_rem(b, c)
+}
+
+// Splits `total` in proportion to `weights`: every part is floor(total * weight / sum of weights), and the units
+// left over go one each to the parts with the largest remainders, ties to the earlier part. The parts add up to
+// exactly `total`. None if the weights add up to zero or overflow.
+pub fn split_largest_remainder(total: Amount, weights: &[Amount]) -> Option<Vec<Amount>> {
+  let weight_sum = weights.iter().try_fold(ZERO, |sum, weight| sum.checked_add(*weight))?;
+  let mut left_over = total;
+  let mut parts = Vec::with_capacity(weights.len());
+  let mut remainders = Vec::with_capacity(weights.len());
+  for (i, weight) in weights.iter().enumerate() {
+    let (part, remainder) = mul_div_rem(total, *weight, weight_sum)?;
+    left_over = left_over.checked_sub(part)?;
+    parts.push(part);
+    remainders.push((remainder, i));
+  }
+  remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+  let one = Amount::from(1u8);
+  for (_, i) in remainders {
+    if left_over == ZERO { break; }
+    parts[i] = parts[i].checked_add(one)?;
+    left_over = left_over.checked_sub(one)?;
+  }
+  Some(parts)
+}
+
+// Signed counterpart of `Amount` for balance changes, stored as sign and magnitude so that every amount can be
+// credited or debited without a wider type. Zero is never negative.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SignedAmount {
+  negative: bool,
+  magnitude: Amount,
+}
+
+impl SignedAmount {
+  pub fn positive(magnitude: Amount) -> SignedAmount { SignedAmount::new(false, magnitude) }
+  pub fn negative(magnitude: Amount) -> SignedAmount { SignedAmount::new(true, magnitude) }
+
+  fn new(negative: bool, magnitude: Amount) -> SignedAmount {
+    SignedAmount { negative: negative && magnitude != ZERO, magnitude }
+  }
+
+  pub fn is_zero(&self) -> bool { self.magnitude == ZERO }
+  pub fn is_negative(&self) -> bool { self.negative }
+  pub fn magnitude(&self) -> Amount { self.magnitude }
+
+  // the amount itself if it is not negative
+  pub fn to_amount(self) -> Option<Amount> {
+    if self.negative { None } else { Some(self.magnitude) }
+  }
+
+  // cannot overflow: the magnitude is unchanged
+  pub fn neg(self) -> SignedAmount {
+    SignedAmount::new(!self.negative, self.magnitude)
+  }
+
+  pub fn checked_add(self, other: SignedAmount) -> Option<SignedAmount> {
+    if self.negative == other.negative {
+      return Some(SignedAmount::new(self.negative, self.magnitude.checked_add(other.magnitude)?));
+    }
+    // opposite signs: the larger magnitude keeps its sign and the difference always fits
+    let (larger, smaller) = if self.magnitude >= other.magnitude { (self, other) } else { (other, self) };
+    Some(SignedAmount::new(larger.negative, larger.magnitude.checked_sub(smaller.magnitude)?))
+  }
+
+  pub fn checked_sub(self, other: SignedAmount) -> Option<SignedAmount> {
+    self.checked_add(other.neg())
+  }
+}
+
+impl From<Amount> for SignedAmount {
+  fn from(amount: Amount) -> SignedAmount { SignedAmount::positive(amount) }
+}
+
+impl Ord for SignedAmount {
+  fn cmp(&self, other: &SignedAmount) -> Ordering {
+    match (self.negative, other.negative) {
+      (false, false) => self.magnitude.cmp(&other.magnitude),
+      (true, true) => other.magnitude.cmp(&self.magnitude),
+      (false, true) => Ordering::Greater,
+      (true, false) => Ordering::Less,
+    }
+  }
+}
+
+impl PartialOrd for SignedAmount {
+  fn partial_cmp(&self, other: &SignedAmount) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl fmt::Display for SignedAmount {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", if self.negative { "-" } else { "" }, self.magnitude)
+  }
+}
+
+// An optional '-' followed by the digits of an `Amount`, e.g. "-1200"
+impl FromStr for SignedAmount {
+  type Err = <Amount as FromStr>::Err;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.strip_prefix('-') {
+      Some(magnitude) => magnitude.parse().map(SignedAmount::negative),
+      None => s.parse().map(SignedAmount::positive),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{amount, amounts};
+
+  fn signed(value: &str) -> SignedAmount {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn add_and_sub_across_signs() {
+    assert_eq!(signed("5").checked_add(signed("-8")), Some(signed("-3")));
+    assert_eq!(signed("-5").checked_add(signed("8")), Some(signed("3")));
+    assert_eq!(signed("-5").checked_add(signed("5")), Some(SignedAmount::default()));
+    assert_eq!(signed("-5").checked_sub(signed("5")), Some(signed("-10")));
+    assert!(!signed("-0").is_negative());
+    assert_eq!(signed("-0"), SignedAmount::default());
+  }
+
+  #[test]
+  fn full_amount_range_fits_either_sign() {
+    let max = SignedAmount::positive(Amount::MAX);
+    assert_eq!(max.neg().to_string(), format!("-{}", Amount::MAX));
+    assert_eq!(max.checked_add(max.neg()), Some(SignedAmount::default()));
+    assert_eq!(max.checked_add(signed("1")), None);
+    assert_eq!(max.neg().checked_sub(signed("1")), None);
+    assert_eq!(max.neg().to_amount(), None);
+    assert_eq!(max.to_amount(), Some(Amount::MAX));
+  }
+
+  #[test]
+  fn split_largest_remainder_loses_no_dust() {
+    let split = |total, weights: &[u128]| split_largest_remainder(amount(total), &amounts(weights));
+    assert_eq!(split(10, &[1, 1, 1]), Some(amounts(&[4, 3, 3])));
+    assert_eq!(split(10, &[5, 3, 2]), Some(amounts(&[5, 3, 2])));
+    assert_eq!(split(7, &[60, 30, 10]), Some(amounts(&[4, 2, 1])));
+    assert_eq!(split(1, &[1, 3]), Some(amounts(&[0, 1])));
+    assert_eq!(split(0, &[1, 3]), Some(amounts(&[0, 0])));
+    assert_eq!(split(1, &[0, 0]), None);
+    let (half, _) = mul_div_rem(Amount::MAX, amount(1), amount(2)).unwrap();
+    assert_eq!(split_largest_remainder(Amount::MAX, &amounts(&[1, 1])),
+      Some(vec![half.checked_add(amount(1)).unwrap(), half]));
+    assert_eq!(split_largest_remainder(amount(1), &[Amount::MAX, amount(1)]), None);
+  }
+
+  #[test]
+  fn ordering_follows_the_number_line() {
+    let mut values = [signed("3"), signed("-7"), signed("0"), signed("-2"), signed("10")];
+    values.sort();
+    assert_eq!(values.iter().map(|value| value.to_string()).collect::<Vec<_>>(), vec!["-7", "-2", "0", "3", "10"]);
+  }
+}
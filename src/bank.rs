@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+
+use crate::amount::{Amount, SignedAmount};
+use crate::coins::Coins;
+use crate::error::MultiSendError;
+use crate::events::{multi_send_events, Event, EventSink};
+use crate::receipt::Receipt;
+use crate::{add_amount, compute_balance_changes, debit_spent, Balance, BalanceChange, DenomDefinition, MultiSend};
+
+// Bank owns the account balances and the denom registry and applies `MultiSend` txs to them. A tx is validated,
+// priced and committed in one step: on any error the state is left exactly as it was, and no balance ever
+// becomes negative.
+// Per denom it also tracks the total supply (the sum of all balances), and the cumulative amounts burned and
+// paid as commission since the bank was created.
+#[derive(Debug)]
+pub struct Bank {
+  balances: BTreeMap<String, Coins>,
+  definitions: BTreeMap<String, DenomDefinition>,
+  supply: Coins,
+  burned: Coins,
+  commission: Coins,
+}
+
+// Outcome of `Bank::simulate`: what `execute` would do with the current state, without committing anything.
+#[derive(Debug)]
+pub struct Simulation {
+  // the receipt `execute` would return, whose `changes` are the would-be deltas, or why the tx would be rejected
+  pub result: Result<Receipt, MultiSendError>,
+  // one entry per sender and denom it pays in, sorted by address and then by denom; empty if the tx is malformed
+  pub headroom: Vec<Headroom>,
+}
+
+// What a sender would have left of a denom after its gross debit, fees included. A negative amount is the shortfall
+// that gets the tx rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Headroom {
+  pub address: String,
+  pub denom: String,
+  pub remaining: SignedAmount,
+}
+
+// Everything `execute` needs to commit a tx: its receipt, its events and the new balances of the changed addresses.
+struct PreparedTx {
+  receipt: Receipt,
+  events: Vec<Event>,
+  updated: Vec<(String, Coins)>,
+}
+
+impl Bank {
+  // Balances listed more than once for the same address are added up.
+  pub fn new(balances: Vec<Balance>, definitions: Vec<DenomDefinition>) -> Result<Bank, MultiSendError> {
+    let mut accounts: BTreeMap<String, Coins> = BTreeMap::new();
+    let mut supply = Coins::default();
+    for balance in balances {
+      let account = accounts.entry(balance.address.clone()).or_default();
+      for coin in &balance.coins {
+        add_amount(account, &coin.denom, coin.amount)?;
+        add_amount(&mut supply, &coin.denom, coin.amount)?;
+      }
+    }
+    Ok(Bank {
+      balances: accounts,
+      definitions: DenomDefinition::token_map(definitions)?,
+      supply,
+      burned: Coins::default(),
+      commission: Coins::default(),
+    })
+  }
+
+  pub fn balance_of(&self, address: &str) -> Coins {
+    self.balances.get(address).cloned().unwrap_or_default()
+  }
+
+  pub fn definition_of(&self, denom: &str) -> Option<&DenomDefinition> {
+    self.definitions.get(denom)
+  }
+
+  pub fn supply_of(&self, denom: &str) -> Amount { self.supply.amount_of(denom) }
+  // Burn and commission are counted in the denom they are paid in. A denom with a separate fee denom adds its fees
+  // to those of the fee denom: with usdt paying in core, commission_of("usdt") stays 0 and commission_of("core") is
+  // the commission of every denom paid in core. The receipts' commission credits tell the transferred denoms apart.
+  pub fn burned_of(&self, denom: &str) -> Amount { self.burned.amount_of(denom) }
+  pub fn commission_of(&self, denom: &str) -> Amount { self.commission.amount_of(denom) }
+
+  pub fn execute(&mut self, multi_send_tx: MultiSend) -> Result<Receipt, MultiSendError> {
+    self.execute_with_events(multi_send_tx, &mut vec![])
+  }
+
+  // Same as `execute`, and on success emits the events of the tx to `sink` once the state is committed.
+  // A rejected tx emits nothing.
+  pub fn execute_with_events(&mut self, multi_send_tx: MultiSend, sink: &mut dyn EventSink) -> Result<Receipt, MultiSendError> {
+    let PreparedTx { receipt, events, updated } = self.prepare(&multi_send_tx)?;
+    let (mut supply, mut burned, mut commission) = (self.supply.clone(), self.burned.clone(), self.commission.clone());
+    for coin in &receipt.burned {
+      // the burned coins were part of the supply, so this cannot go below zero
+      supply.sub_amount(&coin.denom, coin.amount).ok_or_else(|| MultiSendError::Overflow { denom: coin.denom.clone() })?;
+      add_amount(&mut burned, &coin.denom, coin.amount)?;
+    }
+    for coin in &receipt.commission {
+      add_amount(&mut commission, &coin.denom, coin.amount)?;
+    }
+
+    self.balances.extend(updated);
+    self.supply = supply;
+    self.burned = burned;
+    self.commission = commission;
+    for event in events {
+      sink.emit(event);
+    }
+    Ok(receipt)
+  }
+
+  // Dry run of `execute`: the tx goes through the same checks and the same math, and the state is left untouched
+  // whether it would be accepted or not.
+  pub fn simulate(&self, multi_send_tx: &MultiSend) -> Simulation {
+    Simulation {
+      result: self.prepare(multi_send_tx).map(|prepared| prepared.receipt),
+      headroom: self.headroom(multi_send_tx).unwrap_or_default(),
+    }
+  }
+
+  fn prepare(&self, multi_send_tx: &MultiSend) -> Result<PreparedTx, MultiSendError> {
+    let receipt = compute_balance_changes(&self.balances, &self.definitions, multi_send_tx)?;
+    let events = multi_send_events(multi_send_tx, &receipt)?;
+    let updated = self.apply(&receipt.changes)?;
+    Ok(PreparedTx { receipt, events, updated })
+  }
+
+  // Balance minus gross debit of every sender, also when the balances do not cover the debits.
+  fn headroom(&self, multi_send_tx: &MultiSend) -> Result<Vec<Headroom>, MultiSendError> {
+    let mut spent_per_address: BTreeMap<&str, Coins> = BTreeMap::new();
+    let debits = multi_send_tx.required_debits(&self.definitions)?;
+    for debit in &debits {
+      for (denom, amount) in debit_spent(debit)? {
+        add_amount(spent_per_address.entry(&debit.address).or_default(), denom, amount)?;
+      }
+    }
+    let mut headroom = vec![];
+    for (address, spent) in spent_per_address {
+      let held = self.balance_of(address);
+      for coin in &spent {
+        // both sides fit in an `Amount`, so their difference always fits in a `SignedAmount`
+        let remaining = SignedAmount::positive(held.amount_of(&coin.denom)).checked_sub(SignedAmount::positive(coin.amount))
+          .ok_or_else(|| MultiSendError::Overflow { denom: coin.denom.clone() })?;
+        headroom.push(Headroom { address: address.to_string(), denom: coin.denom.clone(), remaining });
+      }
+    }
+    Ok(headroom)
+  }
+
+  // Computes the new balance of every changed address without touching state, so a failure halfway
+  // cannot leave a partially applied tx behind.
+  fn apply(&self, changes: &[BalanceChange]) -> Result<Vec<(String, Coins)>, MultiSendError> {
+    let mut updated = Vec::with_capacity(changes.len());
+    for change in changes {
+      let mut coins = self.balance_of(&change.address);
+      for coin in &change.coins {
+        if coins.apply(&coin.denom, coin.amount).is_none() {
+          let current = coins.amount_of(&coin.denom).into();
+          return match coin.amount.checked_add(current) {
+            Some(amount) if amount.is_negative() =>
+              Err(MultiSendError::NegativeBalance { address: change.address.clone(), denom: coin.denom.clone(), amount }),
+            _ => Err(MultiSendError::Overflow { denom: coin.denom.clone() }),
+          };
+        }
+      }
+      updated.push((change.address.clone(), coins));
+    }
+    Ok(updated)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::amount::SignedAmount;
+  use crate::testing::{amount, balance, bank, change, coins};
+
+  #[test]
+  fn execute_commits_changes() {
+    let mut bank = bank();
+    let multi_send = MultiSend::new(vec![balance("account1", "500denom1")], vec![balance("account_recipient", "500denom1")]).unwrap();
+
+    let receipt = bank.execute(multi_send).unwrap();
+    assert_eq!(receipt.changes.len(), 3);
+    assert_eq!(bank.balance_of("account1"), coins("400denom1,1000denom2"));
+    assert_eq!(bank.balance_of("account_recipient"), coins("500denom1"));
+    assert_eq!(bank.balance_of("issuer_account_A"), coins("60denom1"));
+
+    // the second tx sees the state left by the first one
+    let multi_send = MultiSend::new(vec![balance("account_recipient", "500denom1")], vec![balance("account1", "500denom1")]).unwrap();
+    let err = bank.execute(multi_send).unwrap_err();
+    assert_eq!(err, MultiSendError::InsufficientBalance {
+      address: "account_recipient".to_string(),
+      denom: "denom1".to_string(),
+      required: amount(600),
+      available: amount(500),
+    });
+  }
+
+  #[test]
+  fn execute_leaves_state_untouched_on_error() {
+    let mut bank = bank();
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "500denom1"), balance("account2", "500denom1")],
+      vec![balance("account_recipient", "1000denom1")],
+    ).unwrap();
+
+    assert!(matches!(bank.execute(multi_send), Err(MultiSendError::InsufficientBalance { .. })));
+    assert_eq!(bank.balance_of("account1"), coins("1000denom1,1000denom2"));
+    assert_eq!(bank.balance_of("account2"), coins("500denom1"));
+    assert_eq!(bank.balance_of("account_recipient"), Coins::default());
+    assert_eq!(bank.balance_of("issuer_account_A"), Coins::default());
+  }
+
+  #[test]
+  fn simulate_reports_the_outcome_without_committing() {
+    let mut bank = bank();
+    // 416 + ceil(33.28) + ceil(49.92) leaves account2 with nothing
+    let multi_send = || MultiSend::new(
+      vec![balance("account1", "500denom1"), balance("account2", "416denom1")],
+      vec![balance("account_recipient", "916denom1")],
+    ).unwrap();
+
+    let simulation = bank.simulate(&multi_send());
+    let headroom: Vec<String> = simulation.headroom.iter().map(|h| format!("{}:{}{}", h.address, h.remaining, h.denom)).collect();
+    assert_eq!(headroom, vec!["account1:400denom1", "account2:0denom1"]);
+    let simulated = simulation.result.unwrap();
+    assert_eq!((bank.balance_of("account1"), bank.supply_of("denom1"), bank.burned_of("denom1")), (coins("1000denom1,1000denom2"), amount(1500), amount(0)));
+
+    let executed = bank.execute(multi_send()).unwrap();
+    assert_eq!(simulated.changes, executed.changes);
+    assert_eq!(simulated.debits, executed.debits);
+    assert_eq!(simulated.commission_credits, executed.commission_credits);
+  }
+
+  #[test]
+  fn simulate_reports_the_shortfall_of_a_rejected_tx() {
+    let bank = bank();
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "500denom1"), balance("account2", "500denom1")],
+      vec![balance("account_recipient", "1000denom1")],
+    ).unwrap();
+
+    let simulation = bank.simulate(&multi_send);
+    assert!(matches!(simulation.result, Err(MultiSendError::InsufficientBalance { required, available, .. })
+      if (required, available) == (amount(600), amount(500))));
+    assert_eq!(simulation.headroom, vec![
+      Headroom { address: "account1".to_string(), denom: "denom1".to_string(), remaining: SignedAmount::positive(amount(400)) },
+      Headroom { address: "account2".to_string(), denom: "denom1".to_string(), remaining: SignedAmount::negative(amount(100)) },
+    ]);
+    assert_eq!(bank.balance_of("account2"), coins("500denom1"));
+
+    // a malformed tx has no headroom to report
+    let multi_send = MultiSend::new(vec![balance("account1", "500denom1")], vec![balance("account_recipient", "400denom1")]).unwrap();
+    let simulation = bank.simulate(&multi_send);
+    assert!(matches!(simulation.result, Err(MultiSendError::AmountMismatch(_))));
+    assert!(simulation.headroom.is_empty());
+  }
+
+  #[test]
+  fn apply_refuses_to_go_negative() {
+    let bank = bank();
+    let err = bank.apply(&[change("account_recipient", "100denom1"), change("account2", "-501denom1")]).unwrap_err();
+    assert_eq!(err, MultiSendError::NegativeBalance { address: "account2".to_string(), denom: "denom1".to_string(), amount: SignedAmount::negative(amount(1)) });
+  }
+
+  #[test]
+  fn new_merges_duplicate_balances() {
+    let bank = Bank::new(vec![balance("account1", "1denom1"), balance("account1", "2denom1,3denom2")], vec![]).unwrap();
+    assert_eq!(bank.balance_of("account1"), coins("3denom1,3denom2"));
+  }
+
+  #[test]
+  fn tracks_supply_burned_and_commission() {
+    let mut bank = bank();
+    assert_eq!(bank.supply_of("denom1"), amount(1500));
+
+    let multi_send = MultiSend::new(vec![balance("account1", "500denom1")], vec![balance("account_recipient", "500denom1")]).unwrap();
+    let receipt = bank.execute(multi_send).unwrap();
+    assert_eq!(receipt.burned, coins("40denom1"));
+    assert_eq!(receipt.commission, coins("60denom1"));
+
+    let multi_send = MultiSend::new(vec![balance("account2", "100denom1")], vec![balance("account1", "100denom1")]).unwrap();
+    bank.execute(multi_send).unwrap();
+    assert_eq!(bank.burned_of("denom1"), amount(48));
+    assert_eq!(bank.commission_of("denom1"), amount(72));
+    assert_eq!(bank.supply_of("denom1"), amount(1500 - 48));
+
+    // supply always equals the sum of all balances
+    let total = ["account1", "account2", "account_recipient", "issuer_account_A"].iter()
+      .try_fold(amount(0), |total, address| total.checked_add(bank.balance_of(address).amount_of("denom1")))
+      .unwrap();
+    assert_eq!(total, bank.supply_of("denom1"));
+
+    // a rejected tx does not move any counter
+    let multi_send = MultiSend::new(vec![balance("account2", "1000denom1")], vec![balance("account1", "1000denom1")]).unwrap();
+    assert!(bank.execute(multi_send).is_err());
+    assert_eq!((bank.supply_of("denom1"), bank.burned_of("denom1"), bank.commission_of("denom1")), (amount(1452), amount(48), amount(72)));
+  }
+}
+
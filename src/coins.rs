@@ -0,0 +1,430 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::amount::{Amount, SignedAmount, ZERO};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub denom: String,
+    pub amount: Amount,
+}
+
+// A coin whose amount may be negative, used for balance changes. It is a distinct type from `Coin`, so a change can
+// never be passed where a balance or a transfer amount is expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedCoin {
+    pub denom: String,
+    pub amount: SignedAmount,
+}
+
+// Reasons a list of coins cannot be turned into `Coins`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinsError {
+  DuplicateDenom(String),
+  ZeroAmount(String),
+}
+
+impl fmt::Display for CoinsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CoinsError::DuplicateDenom(denom) => write!(f, "duplicate denom {}", denom),
+      CoinsError::ZeroAmount(denom) => write!(f, "zero amount for denom {}", denom),
+    }
+  }
+}
+
+impl std::error::Error for CoinsError {}
+
+// Reasons a string such as "1000denom1" or "1000denom1,250denom2" cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCoinError {
+  // the coin does not start with an amount, e.g. "denom1" or ""
+  MissingAmount(String),
+  // the amount of a `Coin` is negative, e.g. "-5denom1"; only `SignedCoin` accepts a sign
+  NegativeAmount(String),
+  // the amount does not fit in the amount type
+  AmountOverflow(String),
+  // the denom does not match the cosmos sdk denom grammar, e.g. "1000" or "1000d"
+  InvalidDenom(String),
+  // the individual coins parsed, but do not form valid `Coins`
+  InvalidCoins(CoinsError),
+}
+
+impl fmt::Display for ParseCoinError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseCoinError::MissingAmount(coin) => write!(f, "invalid coin {:?}: missing amount", coin),
+      ParseCoinError::NegativeAmount(coin) => write!(f, "invalid coin {:?}: negative amount", coin),
+      ParseCoinError::AmountOverflow(coin) => write!(f, "invalid coin {:?}: amount out of range", coin),
+      ParseCoinError::InvalidDenom(denom) => write!(f, "invalid denom {:?}", denom),
+      ParseCoinError::InvalidCoins(err) => write!(f, "invalid coins: {}", err),
+    }
+  }
+}
+
+impl std::error::Error for ParseCoinError {}
+
+// Same grammar as cosmos sdk: [a-zA-Z][a-zA-Z0-9/:._-]{2,127}
+pub fn is_valid_denom(denom: &str) -> bool {
+  let bytes = denom.as_bytes();
+  (3..=128).contains(&bytes.len())
+    && bytes[0].is_ascii_alphabetic()
+    && bytes[1..].iter().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'/' | b':' | b'.' | b'_' | b'-'))
+}
+
+// "<amount><denom>", e.g. "1000denom1" or "250ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2".
+// Whitespace around the coin and between amount and denom is ignored, like in cosmos sdk.
+impl FromStr for Coin {
+  type Err = ParseCoinError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let coin = s.trim();
+    if coin.starts_with('-') {
+      return Err(ParseCoinError::NegativeAmount(coin.to_string()));
+    }
+    let digits = coin.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+      return Err(ParseCoinError::MissingAmount(coin.to_string()));
+    }
+    let amount: Amount = coin[..digits].parse().map_err(|_| ParseCoinError::AmountOverflow(coin.to_string()))?;
+    let denom = coin[digits..].trim_start();
+    if !is_valid_denom(denom) {
+      return Err(ParseCoinError::InvalidDenom(denom.to_string()));
+    }
+    Ok(Coin { denom: denom.to_string(), amount })
+  }
+}
+
+impl fmt::Display for Coin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", self.amount, self.denom)
+  }
+}
+
+// Same as `Coin`, with an optional leading '-', e.g. "-1200denom1".
+impl FromStr for SignedCoin {
+  type Err = ParseCoinError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let coin = s.trim();
+    match coin.strip_prefix('-') {
+      Some(unsigned) => unsigned.parse::<Coin>().map(|coin| SignedCoin { denom: coin.denom, amount: SignedAmount::negative(coin.amount) }),
+      None => coin.parse::<Coin>().map(|coin| SignedCoin { denom: coin.denom, amount: SignedAmount::positive(coin.amount) }),
+    }
+  }
+}
+
+impl fmt::Display for SignedCoin {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", self.amount, self.denom)
+  }
+}
+
+// Coins is a canonical set of coins, similar to `sdk.Coins` in cosmos sdk: sorted by denom, at most one coin per
+// denom and never a zero amount. Amounts are unsigned, so `Coins` holds balances and transfer amounts; balance
+// changes use `SignedCoins`.
+// Arithmetic is checked and returns `None` on overflow or when a denom would go below zero; results drop denoms that
+// reach zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coins(Vec<Coin>);
+
+impl Coins {
+  // Sorts `coins` by denom; rejects duplicate denoms and zero amounts instead of silently merging or dropping them.
+  pub fn new(mut coins: Vec<Coin>) -> Result<Coins, CoinsError> {
+    coins.sort_by(|a, b| a.denom.cmp(&b.denom));
+    for (i, coin) in coins.iter().enumerate() {
+      if coin.amount == ZERO {
+        return Err(CoinsError::ZeroAmount(coin.denom.clone()));
+      }
+      if i > 0 && coins[i - 1].denom == coin.denom {
+        return Err(CoinsError::DuplicateDenom(coin.denom.clone()));
+      }
+    }
+    Ok(Coins(coins))
+  }
+
+  pub fn is_empty(&self) -> bool { self.0.is_empty() }
+  pub fn len(&self) -> usize { self.0.len() }
+  pub fn iter(&self) -> std::slice::Iter<'_, Coin> { self.0.iter() }
+
+  pub fn amount_of(&self, denom: &str) -> Amount {
+    find(&self.0, denom, |coin| &coin.denom).map_or(ZERO, |index| self.0[index].amount)
+  }
+
+  // Adds `amount` of `denom` in place. Returns `None` and leaves `self` untouched on overflow.
+  pub fn add_amount(&mut self, denom: &str, amount: Amount) -> Option<()> {
+    let sum = self.amount_of(denom).checked_add(amount)?;
+    self.set(denom, sum);
+    Some(())
+  }
+
+  // Takes `amount` of `denom` away in place. Returns `None` and leaves `self` untouched if it holds less.
+  pub fn sub_amount(&mut self, denom: &str, amount: Amount) -> Option<()> {
+    let difference = self.amount_of(denom).checked_sub(amount)?;
+    self.set(denom, difference);
+    Some(())
+  }
+
+  // Adds a signed change, e.g. one entry of a `SignedCoins`; `None` if the denom would go below zero.
+  pub fn apply(&mut self, denom: &str, change: SignedAmount) -> Option<()> {
+    if change.is_negative() {
+      self.sub_amount(denom, change.magnitude())
+    } else {
+      self.add_amount(denom, change.magnitude())
+    }
+  }
+
+  pub fn checked_add(&self, other: &Coins) -> Option<Coins> {
+    let mut result = self.clone();
+    for coin in other.iter() {
+      result.add_amount(&coin.denom, coin.amount)?;
+    }
+    Some(result)
+  }
+
+  // Difference per denom; `None` if any denom of the result would be negative, e.g. when debiting a balance.
+  pub fn checked_sub(&self, other: &Coins) -> Option<Coins> {
+    self.safe_sub(other).ok()
+  }
+
+  // Like `checked_sub`, but on failure returns the first coin of `other` (by denom) that `self` holds less of, so a
+  // caller can report which denom a balance falls short in.
+  pub fn safe_sub(&self, other: &Coins) -> Result<Coins, Coin> {
+    let mut result = self.clone();
+    for coin in other.iter() {
+      if result.sub_amount(&coin.denom, coin.amount).is_none() {
+        return Err(coin.clone());
+      }
+    }
+    Ok(result)
+  }
+
+  // true if self holds at least as much as `other` for every denom of `other`
+  pub fn is_all_gte(&self, other: &Coins) -> bool {
+    other.iter().all(|coin| self.amount_of(&coin.denom) >= coin.amount)
+  }
+
+  fn set(&mut self, denom: &str, amount: Amount) {
+    match find(&self.0, denom, |coin| &coin.denom) {
+      Ok(index) if amount == ZERO => { self.0.remove(index); }
+      Ok(index) => self.0[index].amount = amount,
+      Err(_) if amount == ZERO => {}
+      Err(index) => self.0.insert(index, Coin { denom: denom.to_string(), amount }),
+    }
+  }
+}
+
+// The signed counterpart of `Coins` for balance changes: sorted by denom, at most one coin per denom and never a
+// zero amount, but amounts may be negative (a deduction). It cannot be mixed up with `Coins` by accident; converting
+// goes through `Coins::apply` or `SignedCoins::credit`/`SignedCoins::debit`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignedCoins(Vec<SignedCoin>);
+
+impl SignedCoins {
+  pub fn new(mut coins: Vec<SignedCoin>) -> Result<SignedCoins, CoinsError> {
+    coins.sort_by(|a, b| a.denom.cmp(&b.denom));
+    for (i, coin) in coins.iter().enumerate() {
+      if coin.amount.is_zero() {
+        return Err(CoinsError::ZeroAmount(coin.denom.clone()));
+      }
+      if i > 0 && coins[i - 1].denom == coin.denom {
+        return Err(CoinsError::DuplicateDenom(coin.denom.clone()));
+      }
+    }
+    Ok(SignedCoins(coins))
+  }
+
+  pub fn is_empty(&self) -> bool { self.0.is_empty() }
+  pub fn len(&self) -> usize { self.0.len() }
+  pub fn iter(&self) -> std::slice::Iter<'_, SignedCoin> { self.0.iter() }
+
+  pub fn amount_of(&self, denom: &str) -> SignedAmount {
+    find(&self.0, denom, |coin| &coin.denom).map_or_else(|_| SignedAmount::default(), |index| self.0[index].amount)
+  }
+
+  // Adds `amount` of `denom` in place. Returns `None` and leaves `self` untouched on overflow.
+  pub fn add_amount(&mut self, denom: &str, amount: SignedAmount) -> Option<()> {
+    let sum = self.amount_of(denom).checked_add(amount)?;
+    match find(&self.0, denom, |coin| &coin.denom) {
+      Ok(index) if sum.is_zero() => { self.0.remove(index); }
+      Ok(index) => self.0[index].amount = sum,
+      Err(_) if sum.is_zero() => {}
+      Err(index) => self.0.insert(index, SignedCoin { denom: denom.to_string(), amount: sum }),
+    }
+    Some(())
+  }
+
+  pub fn credit(&mut self, denom: &str, amount: Amount) -> Option<()> {
+    self.add_amount(denom, SignedAmount::positive(amount))
+  }
+
+  pub fn debit(&mut self, denom: &str, amount: Amount) -> Option<()> {
+    self.add_amount(denom, SignedAmount::negative(amount))
+  }
+}
+
+fn find<T>(coins: &[T], denom: &str, denom_of: impl Fn(&T) -> &String) -> Result<usize, usize> {
+  coins.binary_search_by(|coin| denom_of(coin).as_str().cmp(denom))
+}
+
+// Comma separated coins, e.g. "1000denom1,250denom2". The empty string is the empty set.
+impl FromStr for Coins {
+  type Err = ParseCoinError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.trim().is_empty() {
+      return Ok(Coins::default());
+    }
+    let coins = s.split(',').map(str::parse).collect::<Result<Vec<Coin>, _>>()?;
+    Coins::new(coins).map_err(ParseCoinError::InvalidCoins)
+  }
+}
+
+// Comma separated signed coins, e.g. "-1200denom1,250denom2"; the same format `Display` writes.
+impl FromStr for SignedCoins {
+  type Err = ParseCoinError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.trim().is_empty() {
+      return Ok(SignedCoins::default());
+    }
+    let coins = s.split(',').map(str::parse).collect::<Result<Vec<SignedCoin>, _>>()?;
+    SignedCoins::new(coins).map_err(ParseCoinError::InvalidCoins)
+  }
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, coins: &[T]) -> fmt::Result {
+  for (i, coin) in coins.iter().enumerate() {
+    if i > 0 {
+      write!(f, ",")?;
+    }
+    write!(f, "{}", coin)?;
+  }
+  Ok(())
+}
+
+impl fmt::Display for Coins {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write_list(f, &self.0) }
+}
+
+impl fmt::Display for SignedCoins {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write_list(f, &self.0) }
+}
+
+impl<'a> IntoIterator for &'a Coins {
+  type Item = &'a Coin;
+  type IntoIter = std::slice::Iter<'a, Coin>;
+
+  fn into_iter(self) -> Self::IntoIter { self.0.iter() }
+}
+
+impl<'a> IntoIterator for &'a SignedCoins {
+  type Item = &'a SignedCoin;
+  type IntoIter = std::slice::Iter<'a, SignedCoin>;
+
+  fn into_iter(self) -> Self::IntoIter { self.0.iter() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{amount, coins, signed_coins};
+
+  #[test]
+  fn new_sorts_and_rejects_invalid_coins() {
+    let sorted = coins("5denom2,7denom1");
+    let denoms: Vec<&str> = sorted.iter().map(|coin| coin.denom.as_str()).collect();
+    assert_eq!(denoms, vec!["denom1", "denom2"]);
+
+    let duplicate = vec![Coin { denom: "denom1".to_string(), amount: amount(1) }, Coin { denom: "denom1".to_string(), amount: amount(2) }];
+    assert_eq!(Coins::new(duplicate), Err(CoinsError::DuplicateDenom("denom1".to_string())));
+    assert_eq!(Coins::new(vec![Coin { denom: "denom1".to_string(), amount: amount(0) }]), Err(CoinsError::ZeroAmount("denom1".to_string())));
+    let zero = SignedCoin { denom: "denom1".to_string(), amount: SignedAmount::default() };
+    assert_eq!(SignedCoins::new(vec![zero]), Err(CoinsError::ZeroAmount("denom1".to_string())));
+  }
+
+  #[test]
+  fn add_and_sub_keep_canonical_form() {
+    let a = coins("10denom1,5denom3");
+    assert_eq!(a.checked_add(&coins("3denom2,5denom3")), Some(coins("10denom1,3denom2,10denom3")));
+    assert_eq!(a.checked_sub(&a), Some(Coins::default()));
+    assert_eq!(a.checked_sub(&coins("4denom1")), Some(coins("6denom1,5denom3")));
+    assert_eq!(coins(&format!("{}denom1", Amount::MAX)).checked_add(&coins("1denom1")), None);
+  }
+
+  #[test]
+  fn sub_never_goes_below_zero() {
+    let mut balance = coins("10denom1,5denom2");
+    assert_eq!(balance.checked_sub(&coins("6denom2")), None);
+    assert_eq!(balance.checked_sub(&coins("1denom3")), None);
+    assert_eq!(balance.safe_sub(&coins("1denom1,6denom2,1denom3")), Err(Coin { denom: "denom2".to_string(), amount: amount(6) }));
+    assert_eq!(balance.safe_sub(&coins("1denom1,5denom2")), Ok(coins("9denom1")));
+    assert_eq!(balance.sub_amount("denom1", amount(11)), None);
+    assert_eq!(balance, coins("10denom1,5denom2"));
+
+    assert_eq!(balance.apply("denom1", "-10".parse().unwrap()), Some(()));
+    assert_eq!(balance.apply("denom3", "7".parse().unwrap()), Some(()));
+    assert_eq!(balance.apply("denom2", "-6".parse().unwrap()), None);
+    assert_eq!(balance, coins("5denom2,7denom3"));
+  }
+
+  #[test]
+  fn signed_coins_carry_changes() {
+    let mut change = SignedCoins::default();
+    change.debit("denom1", amount(1200)).unwrap();
+    change.credit("denom2", amount(250)).unwrap();
+    change.credit("denom1", amount(200)).unwrap();
+    assert_eq!(change, signed_coins("250denom2,-1000denom1"));
+    assert_eq!(change.to_string(), "-1000denom1,250denom2");
+    assert_eq!(change.amount_of("denom1"), "-1000".parse().unwrap());
+    assert_eq!(change.amount_of("denom3"), SignedAmount::default());
+
+    change.credit("denom1", amount(1000)).unwrap();
+    assert_eq!(change, signed_coins("250denom2"));
+    change.debit("denom3", Amount::MAX).unwrap();
+    assert_eq!(change.debit("denom3", amount(1)), None);
+  }
+
+  #[test]
+  fn is_all_gte() {
+    let balance = coins("10denom1,5denom2");
+    assert!(balance.is_all_gte(&coins("10denom1,1denom2")));
+    assert!(!balance.is_all_gte(&coins("11denom1")));
+    assert!(!balance.is_all_gte(&coins("1denom3")));
+    assert!(balance.is_all_gte(&Coins::default()));
+    assert_eq!(balance.amount_of("denom3"), amount(0));
+  }
+
+  #[test]
+  fn parse_and_display_coin() {
+    assert_eq!("650denom1".parse::<Coin>(), Ok(Coin { denom: "denom1".to_string(), amount: amount(650) }));
+    assert_eq!(" 1000 ibc/27394FB092D2ECCD ".parse::<Coin>(), Ok(Coin { denom: "ibc/27394FB092D2ECCD".to_string(), amount: amount(1000) }));
+    assert_eq!("-1200denom1".parse::<SignedCoin>(), Ok(SignedCoin { denom: "denom1".to_string(), amount: "-1200".parse().unwrap() }));
+    assert_eq!(SignedCoin { denom: "denom1".to_string(), amount: "-1200".parse().unwrap() }.to_string(), "-1200denom1");
+
+    assert_eq!("-1200denom1".parse::<Coin>(), Err(ParseCoinError::NegativeAmount("-1200denom1".to_string())));
+    assert_eq!("denom1".parse::<Coin>(), Err(ParseCoinError::MissingAmount("denom1".to_string())));
+    assert_eq!("".parse::<Coin>(), Err(ParseCoinError::MissingAmount("".to_string())));
+    assert_eq!("1000".parse::<Coin>(), Err(ParseCoinError::InvalidDenom("".to_string())));
+    assert_eq!("10de".parse::<Coin>(), Err(ParseCoinError::InvalidDenom("de".to_string())));
+    assert_eq!("10 1denom".parse::<Coin>(), Err(ParseCoinError::InvalidDenom("1denom".to_string())));
+    assert_eq!("10den#m".parse::<Coin>(), Err(ParseCoinError::InvalidDenom("den#m".to_string())));
+    assert_eq!("1.5denom1".parse::<Coin>(), Err(ParseCoinError::InvalidDenom(".5denom1".to_string())));
+    assert_eq!(format!("{}denom1", Amount::MAX).parse::<Coin>(), Ok(Coin { denom: "denom1".to_string(), amount: Amount::MAX }));
+    assert_eq!(format!("{}0denom1", Amount::MAX).parse::<Coin>(), Err(ParseCoinError::AmountOverflow(format!("{}0denom1", Amount::MAX))));
+  }
+
+  #[test]
+  fn parse_and_display_coins() {
+    let parsed = coins("250denom2,1000denom1");
+    assert_eq!(parsed.to_string(), "1000denom1,250denom2");
+    assert_eq!(parsed.to_string().parse::<Coins>(), Ok(parsed));
+    assert_eq!("".parse::<Coins>(), Ok(Coins::default()));
+    assert_eq!(Coins::default().to_string(), "");
+
+    assert_eq!("1denom1,2denom1".parse::<Coins>(), Err(ParseCoinError::InvalidCoins(CoinsError::DuplicateDenom("denom1".to_string()))));
+    assert_eq!("0denom1".parse::<Coins>(), Err(ParseCoinError::InvalidCoins(CoinsError::ZeroAmount("denom1".to_string()))));
+    assert_eq!("1denom1,".parse::<Coins>(), Err(ParseCoinError::MissingAmount("".to_string())));
+    assert_eq!("-0denom1".parse::<SignedCoins>(), Err(ParseCoinError::InvalidCoins(CoinsError::ZeroAmount("denom1".to_string()))));
+  }
+}
@@ -0,0 +1,323 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::amount::{self, Amount, ZERO};
+
+// Decimal is a signed fixed-point number with 18 fractional digits, similar to `sdk.Dec` in cosmos sdk.
+// The value is stored as an integer number of 10^-18 units ("atomics"), so every operation is exact integer
+// math and gives the same result on every machine. Multiplication and division keep the full 256-bit
+// intermediate product and truncate toward zero only once, at the 18th digit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(i128);
+
+// How a non-integer result is turned into an integer amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+  // toward zero
+  Floor,
+  // away from zero
+  Ceil,
+  // to the nearest integer, ties to the even one (banker's rounding)
+  HalfEven,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseDecimalError {
+  input: String,
+}
+
+impl fmt::Display for ParseDecimalError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid decimal: {:?}", self.input)
+  }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+impl Decimal {
+  pub const PRECISION: u32 = 18;
+  const ONE_ATOMICS: i128 = 10i128.pow(Decimal::PRECISION);
+
+  pub const fn zero() -> Decimal { Decimal(0) }
+  pub const fn one() -> Decimal { Decimal(Decimal::ONE_ATOMICS) }
+  pub const fn from_atomics(atomics: i128) -> Decimal { Decimal(atomics) }
+  pub const fn atomics(&self) -> i128 { self.0 }
+
+  // percent(8) == 0.08, permille(5) == 0.005. These are meant for literal rates, in constants and tests: an
+  // out-of-range value fails to compile in a const context and panics otherwise. Rates computed at runtime go
+  // through the checked `from_ratio`.
+  pub const fn percent(value: i128) -> Decimal { Decimal::literal(value, 100) }
+  pub const fn permille(value: i128) -> Decimal { Decimal::literal(value, 1000) }
+
+  const fn literal(value: i128, denominator: i128) -> Decimal {
+    match value.checked_mul(Decimal::ONE_ATOMICS / denominator) {
+      Some(atomics) => Decimal(atomics),
+      None => panic!("decimal literal out of range"),
+    }
+  }
+
+  pub fn from_int(value: i128) -> Option<Decimal> {
+    value.checked_mul(Decimal::ONE_ATOMICS).map(Decimal)
+  }
+
+  pub fn from_ratio(numerator: i128, denominator: i128) -> Option<Decimal> {
+    Decimal::one().checked_mul_ratio(numerator, denominator)
+  }
+
+  pub fn is_zero(&self) -> bool { self.0 == 0 }
+  pub fn is_negative(&self) -> bool { self.0 < 0 }
+
+  pub fn checked_add(self, other: Decimal) -> Option<Decimal> { self.0.checked_add(other.0).map(Decimal) }
+  pub fn checked_sub(self, other: Decimal) -> Option<Decimal> { self.0.checked_sub(other.0).map(Decimal) }
+  pub fn checked_mul(self, other: Decimal) -> Option<Decimal> { self.checked_mul_ratio(other.0, Decimal::ONE_ATOMICS) }
+  pub fn checked_div(self, other: Decimal) -> Option<Decimal> { self.checked_mul_ratio(Decimal::ONE_ATOMICS, other.0) }
+  pub fn checked_mul_int(self, value: i128) -> Option<Decimal> { self.0.checked_mul(value).map(Decimal) }
+
+  // self * numerator / denominator, truncated toward zero. The product is never truncated before the division.
+  pub fn checked_mul_ratio(self, numerator: i128, denominator: i128) -> Option<Decimal> {
+    let negative = (self.0 < 0) ^ (numerator < 0) ^ (denominator < 0);
+    let magnitude = mul_div(self.0.unsigned_abs(), numerator.unsigned_abs(), denominator.unsigned_abs())?;
+    if negative {
+      0i128.checked_sub_unsigned(magnitude).map(Decimal)
+    } else {
+      i128::try_from(magnitude).ok().map(Decimal)
+    }
+  }
+
+  // ceil(self * a * b / denominator), see `checked_mul_rounded`
+  pub fn checked_mul_ceil(self, a: Amount, b: Amount, denominator: Amount) -> Option<Amount> {
+    self.checked_mul_rounded(a, b, denominator, Rounding::Ceil)
+  }
+
+  // self * a * b / denominator, rounded to an integer in a single step, for a non-negative decimal. None if the
+  // decimal is negative, the denominator is zero or the result does not fit. No intermediate has to fit in a
+  // `Decimal` or an `Amount`, so amounts near `Amount::MAX` work.
+  pub fn checked_mul_rounded(self, a: Amount, b: Amount, denominator: Amount, rounding: Rounding) -> Option<Amount> {
+    if self.0 < 0 {
+      return None;
+    }
+    let (rate, one) = (Amount::from(self.0 as u128), Amount::from(Decimal::ONE_ATOMICS as u128));
+    // a * b = q1 * denominator + r1
+    let (q1, r1) = amount::mul_div_rem(a, b, denominator)?;
+    // rate * q1 = q2 * one + r2
+    let (q2, r2) = amount::mul_div_rem(rate, q1, one)?;
+    // rate * r1 = q3 * denominator + r3
+    let (q3, r3) = amount::mul_div_rem(rate, r1, denominator)?;
+    // the exact value is q2 + (r2 + q3 + r3 / denominator) / one = floor + (fraction + r3 / denominator) / one
+    let (whole, fraction) = amount::mul_div_rem(r2.checked_add(q3)?, Amount::from(1u8), one)?;
+    let floor = q2.checked_add(whole)?;
+    // fraction < one, so the fractional part is below, at or above one half exactly as fraction compares to
+    // one / 2, except that a non-zero r3 lifts an exact half above it
+    let half = Amount::from(Decimal::ONE_ATOMICS as u128 / 2);
+    let round_up = match rounding {
+      Rounding::Floor => false,
+      Rounding::Ceil => fraction != ZERO || r3 != ZERO,
+      Rounding::HalfEven => match fraction.cmp(&half) {
+        Ordering::Less => false,
+        Ordering::Greater => true,
+        Ordering::Equal => r3 != ZERO || amount::mul_div_rem(floor, Amount::from(1u8), Amount::from(2u8))?.1 != ZERO,
+      },
+    };
+    if round_up { floor.checked_add(Amount::from(1u8)) } else { Some(floor) }
+  }
+
+  // largest integer less than or equal to the value
+  pub fn floor(&self) -> i128 {
+    self.0.div_euclid(Decimal::ONE_ATOMICS)
+  }
+
+  // smallest integer greater than or equal to the value
+  pub fn ceil(&self) -> i128 {
+    let floor = self.floor();
+    if self.0.rem_euclid(Decimal::ONE_ATOMICS) == 0 { floor } else { floor + 1 }
+  }
+}
+
+impl fmt::Display for Decimal {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let sign = if self.0 < 0 { "-" } else { "" };
+    let magnitude = self.0.unsigned_abs();
+    let one = Decimal::ONE_ATOMICS as u128;
+    write!(f, "{}{}.{:018}", sign, magnitude / one, magnitude % one)
+  }
+}
+
+impl FromStr for Decimal {
+  type Err = ParseDecimalError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || ParseDecimalError { input: s.to_string() };
+    let (negative, unsigned) = match s.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, s),
+    };
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) || fraction.len() > Decimal::PRECISION as usize {
+      return Err(err());
+    }
+    if unsigned.ends_with('.') {
+      return Err(err());
+    }
+
+    let whole: i128 = whole.parse().map_err(|_| err())?;
+    let fraction_atomics: i128 = if fraction.is_empty() {
+      0
+    } else {
+      let padded = format!("{:0<width$}", fraction, width = Decimal::PRECISION as usize);
+      padded.parse().map_err(|_| err())?
+    };
+    let atomics = whole.checked_mul(Decimal::ONE_ATOMICS)
+      .and_then(|atomics| atomics.checked_add(fraction_atomics))
+      .ok_or_else(err)?;
+    Ok(Decimal(if negative { -atomics } else { atomics }))
+  }
+}
+
+// full 256-bit product of two u128 values as (high, low)
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+  let mask = u64::MAX as u128;
+  let (a_hi, a_lo) = (a >> 64, a & mask);
+  let (b_hi, b_lo) = (b >> 64, b & mask);
+  let ll = a_lo * b_lo;
+  let lh = a_lo * b_hi;
+  let hl = a_hi * b_lo;
+  let hh = a_hi * b_hi;
+  let mid = (ll >> 64) + (lh & mask) + (hl & mask);
+  let lo = (ll & mask) | (mid << 64);
+  let hi = hh + (lh >> 64) + (hl >> 64) + (mid >> 64);
+  (hi, lo)
+}
+
+// floor(a * b / c), or None if c is zero or the quotient does not fit in u128
+fn mul_div(a: u128, b: u128, c: u128) -> Option<u128> {
+  mul_div_rem(a, b, c).map(|(quot, _)| quot)
+}
+
+// (floor(a * b / c), a * b mod c), or None if c is zero or the quotient does not fit in u128
+pub fn mul_div_rem(a: u128, b: u128, c: u128) -> Option<(u128, u128)> {
+  if c == 0 {
+    return None;
+  }
+  let (hi, lo) = widening_mul(a, b);
+  if hi == 0 {
+    return Some((lo / c, lo % c));
+  }
+  if hi >= c {
+    return None;
+  }
+  // schoolbook long division of the 256-bit product, one bit at a time
+  let mut rem = hi;
+  let mut quot: u128 = 0;
+  for i in (0..128).rev() {
+    let carry = rem >> 127;
+    rem = (rem << 1) | ((lo >> i) & 1);
+    quot <<= 1;
+    if carry == 1 || rem >= c {
+      rem = rem.wrapping_sub(c);
+      quot |= 1;
+    }
+  }
+  Some((quot, rem))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::amount;
+
+  #[test]
+  #[should_panic(expected = "decimal literal out of range")]
+  fn percent_out_of_range_panics() {
+    Decimal::percent(i128::MAX / 1000);
+  }
+
+  #[test]
+  fn parse_and_display() {
+    assert_eq!("0.08".parse::<Decimal>().unwrap(), Decimal::percent(8));
+    assert_eq!("1".parse::<Decimal>().unwrap(), Decimal::one());
+    assert_eq!("-0.5".parse::<Decimal>().unwrap(), Decimal::from_atomics(-500_000_000_000_000_000));
+    assert_eq!("0.000000000000000001".parse::<Decimal>().unwrap(), Decimal::from_atomics(1));
+    assert_eq!(Decimal::permille(5).to_string(), "0.005000000000000000");
+    assert_eq!(Decimal::from_atomics(-1).to_string(), "-0.000000000000000001");
+
+    for invalid in ["", ".5", "1.", "1.2.3", "abc", "0.0000000000000000001", "--1"] {
+      assert!(invalid.parse::<Decimal>().is_err(), "{} should not parse", invalid);
+    }
+  }
+
+  #[test]
+  fn rounding() {
+    assert_eq!("7.5".parse::<Decimal>().unwrap().ceil(), 8);
+    assert_eq!("7.5".parse::<Decimal>().unwrap().floor(), 7);
+    assert_eq!("-7.5".parse::<Decimal>().unwrap().ceil(), -7);
+    assert_eq!("-7.5".parse::<Decimal>().unwrap().floor(), -8);
+    assert_eq!(Decimal::from_int(3).unwrap().ceil(), 3);
+    assert_eq!(Decimal::from_atomics(1).ceil(), 1);
+  }
+
+  #[test]
+  fn checked_arithmetic() {
+    let rate = Decimal::percent(12);
+    assert_eq!(rate.checked_mul(Decimal::from_int(1000).unwrap()), Decimal::from_int(120));
+    assert_eq!(Decimal::one().checked_div(Decimal::from_int(3).unwrap()), Some(Decimal::from_atomics(333_333_333_333_333_333)));
+    assert_eq!(Decimal::one().checked_div(Decimal::zero()), None);
+    assert_eq!(Decimal::from_ratio(-1, 4), Some(Decimal::from_atomics(-250_000_000_000_000_000)));
+    assert_eq!(Decimal::from_int(i128::MAX), None);
+    assert_eq!(Decimal::from_atomics(i128::MAX).checked_add(Decimal::from_atomics(1)), None);
+  }
+
+  #[test]
+  fn mul_ratio_keeps_full_precision() {
+    // (2^100 * 2^100) / 2^100 overflows any 128-bit intermediate but the result fits
+    let big = 1i128 << 100;
+    assert_eq!(Decimal::from_atomics(big).checked_mul_ratio(big, big), Some(Decimal::from_atomics(big)));
+    // amounts above 2^53 are not representable as f64 but stay exact here
+    let amount = (1i128 << 60) + 1;
+    assert_eq!(Decimal::percent(10).checked_mul_int(amount).unwrap().checked_mul_ratio(1, 1).unwrap().ceil(), amount / 10 + 1);
+    assert_eq!(Decimal::from_atomics(i128::MAX).checked_mul_ratio(2, 1), None);
+  }
+
+  #[test]
+  fn mul_ceil_handles_the_full_amount_range() {
+    let rate = Decimal::percent(8);
+    assert_eq!(rate.checked_mul_ceil(amount(75), amount(60), amount(150)), Some(amount(3)));
+    assert_eq!(rate.checked_mul_ceil(amount(100), amount(1), amount(1)), Some(amount(8)));
+    assert_eq!(Decimal::percent(10).checked_mul_ceil(amount(75), amount(90), amount(150)), Some(amount(5)));
+
+    // rate * min alone is far beyond 128 bits in atomics, the share itself fits
+    let max = amount(u128::MAX);
+    assert_eq!(Decimal::one().checked_mul_ceil(max, max, max), Some(max));
+    assert_eq!(Decimal::percent(50).checked_mul_ceil(max, max, max), Some(amount(u128::MAX / 2 + 1)));
+    assert_eq!(rate.checked_mul_ceil(max, amount(1), amount(1)), Some(amount(27_222_589_353_675_077_077_069_968_594_541_456_917)));
+    // a * b goes past u128::MAX before the division brings it back
+    assert_eq!(Decimal::one().checked_mul_ceil(max, amount(2), amount(2)), Some(max));
+    assert_eq!(Decimal::one().checked_mul_ceil(max, amount(2), amount(3)),
+      Some(amount(226_854_911_280_625_642_308_916_404_954_512_140_970)));
+
+    // results above Amount::MAX, negative rates and a zero denominator are rejected
+    assert_eq!(Decimal::from_int(2).unwrap().checked_mul_ceil(Amount::MAX, amount(1), amount(1)), None);
+    assert_eq!(Decimal::one().checked_mul_ceil(Amount::MAX, Amount::MAX, amount(1)), None);
+    assert_eq!(Decimal::from_int(-1).unwrap().checked_mul_ceil(amount(1), amount(1), amount(1)), None);
+    assert_eq!(rate.checked_mul_ceil(amount(1), amount(1), amount(0)), None);
+  }
+
+  #[test]
+  fn mul_rounded_modes() {
+    let rate = Decimal::percent(10);
+    // 10% of 45 = 4.5, of 55 = 5.5, of 46 = 4.6, of 44 = 4.4
+    let round = |value, rounding| rate.checked_mul_rounded(amount(value), amount(1), amount(1), rounding).unwrap();
+    assert_eq!([45, 55, 46, 44].map(|value| round(value, Rounding::Floor)), [4, 5, 4, 4].map(amount));
+    assert_eq!([45, 55, 46, 44].map(|value| round(value, Rounding::Ceil)), [5, 6, 5, 5].map(amount));
+    assert_eq!([45, 55, 46, 44].map(|value| round(value, Rounding::HalfEven)), [4, 6, 5, 4].map(amount));
+    assert_eq!(round(50, Rounding::HalfEven), amount(5));
+
+    // 0.5 * 1 is a tie, 0.5 * (1 + 10^-20) is just above it even though the excess is below the 18th digit
+    let half = Decimal::percent(50);
+    let (one, scale) = (amount(1), amount(10u128.pow(20)));
+    assert_eq!(half.checked_mul_rounded(one, one, one, Rounding::HalfEven), Some(amount(0)));
+    assert_eq!(half.checked_mul_rounded(amount(10u128.pow(20) + 1), one, scale, Rounding::HalfEven), Some(one));
+    assert_eq!(half.checked_mul_rounded(amount(10u128.pow(20) + 1), one, scale, Rounding::Floor), Some(amount(0)));
+  }
+}
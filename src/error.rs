@@ -0,0 +1,166 @@
+use std::fmt;
+
+use crate::amount::{Amount, SignedAmount};
+use crate::decimal::Decimal;
+
+// Codespace reported together with `MultiSendError::code`, in the same spirit as ABCI codespace/code pairs.
+pub const CODESPACE: &str = "multisend";
+
+// Input and output totals of a single denom that do not match. A denom that only appears on one side
+// reports zero for the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DenomMismatch {
+  pub denom: String,
+  pub inputs: Amount,
+  pub outputs: Amount,
+}
+
+// Reasons for rejecting a `MultiSend` transaction. Every variant has a stable numeric code (see `code`), so
+// callers should match on the variant or the code and never on the `Display` text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultiSendError {
+  // the sum of inputs and the sum of outputs differ; lists every mismatching denom, sorted by denom
+  AmountMismatch(Vec<DenomMismatch>),
+  // `address` must pay `required` (transfer amount plus burn and commission) but only holds `available`
+  InsufficientBalance { address: String, denom: String, required: Amount, available: Amount },
+  // the tx references a denom that has no `DenomDefinition`
+  UnknownDenom { denom: String },
+  // an input address does not exist in the original balances
+  UnknownAccount { address: String },
+  // an amount computation for `denom` does not fit in the amount type
+  Overflow { denom: String },
+  // the tx has no inputs
+  NoInputs,
+  // the tx has no outputs
+  NoOutputs,
+  // an input or output, or the issuer or a commission recipient of a denom definition, has an empty address
+  EmptyAddress,
+  // an input or output of `address` carries no coins
+  EmptyCoins { address: String },
+  // a coin amount is zero
+  InvalidAmount { address: String, denom: String, amount: Amount },
+  // the same denom appears more than once in a single input or output
+  DuplicateDenom { address: String, denom: String },
+  // a denom does not match the cosmos sdk denom grammar
+  InvalidDenom { denom: String },
+  // applying the changes would leave `address` with a negative `amount` of `denom`
+  NegativeBalance { address: String, denom: String, amount: SignedAmount },
+  // the balance changes of `denom` add up to `delta` instead of minus the `burned` amount; the tx would create
+  // or destroy coins
+  SupplyMismatch { denom: String, delta: SignedAmount, burned: Amount },
+  // a burn or commission rate of `denom` is outside [0, 1], or its fee conversion rate is not positive
+  InvalidRate { denom: String, rate: Decimal },
+  // the commission recipients of `denom` all have a zero weight, so nobody could receive the commission
+  ZeroCommissionWeights { denom: String },
+  // more than one `DenomDefinition` is given for `denom`
+  DuplicateDefinition { denom: String },
+}
+
+impl MultiSendError {
+  pub fn codespace(&self) -> &'static str { CODESPACE }
+
+  // Codes are part of the public contract: never renumber or reuse them, only append new ones.
+  // Code 1 is left free for internal errors, following the ABCI convention.
+  pub fn code(&self) -> u32 {
+    match self {
+      MultiSendError::AmountMismatch(_) => 2,
+      MultiSendError::InsufficientBalance { .. } => 3,
+      MultiSendError::UnknownDenom { .. } => 4,
+      MultiSendError::UnknownAccount { .. } => 5,
+      MultiSendError::Overflow { .. } => 6,
+      MultiSendError::NoInputs => 7,
+      MultiSendError::NoOutputs => 8,
+      MultiSendError::EmptyAddress => 9,
+      MultiSendError::EmptyCoins { .. } => 10,
+      MultiSendError::InvalidAmount { .. } => 11,
+      MultiSendError::DuplicateDenom { .. } => 12,
+      MultiSendError::InvalidDenom { .. } => 13,
+      MultiSendError::NegativeBalance { .. } => 14,
+      MultiSendError::SupplyMismatch { .. } => 15,
+      MultiSendError::InvalidRate { .. } => 16,
+      MultiSendError::ZeroCommissionWeights { .. } => 17,
+      MultiSendError::DuplicateDefinition { .. } => 18,
+    }
+  }
+}
+
+impl fmt::Display for MultiSendError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MultiSendError::AmountMismatch(mismatches) => {
+        write!(f, "input and output amounts mismatch:")?;
+        for (i, mismatch) in mismatches.iter().enumerate() {
+          let separator = if i == 0 { " " } else { ", " };
+          write!(f, "{}{} (inputs {}, outputs {})", separator, mismatch.denom, mismatch.inputs, mismatch.outputs)?;
+        }
+        Ok(())
+      }
+      MultiSendError::InsufficientBalance { address, denom, required, available } =>
+        write!(f, "insufficient balance for token {} in address {}: required {}, available {}", denom, address, required, available),
+      MultiSendError::UnknownDenom { denom } => write!(f, "no denom definition found for token {}", denom),
+      MultiSendError::UnknownAccount { address } => write!(f, "address not found in original balances: {}", address),
+      MultiSendError::Overflow { denom } => write!(f, "amount overflow for token {}", denom),
+      MultiSendError::NoInputs => write!(f, "no inputs to send transaction"),
+      MultiSendError::NoOutputs => write!(f, "no outputs to send transaction"),
+      MultiSendError::EmptyAddress => write!(f, "empty address in send transaction"),
+      MultiSendError::EmptyCoins { address } => write!(f, "no coins for address {}", address),
+      MultiSendError::InvalidAmount { address, denom, amount } =>
+        write!(f, "invalid amount {} for token {} in address {}: amounts must be positive", amount, denom, address),
+      MultiSendError::DuplicateDenom { address, denom } => write!(f, "duplicate token {} in address {}", denom, address),
+      MultiSendError::InvalidDenom { denom } => write!(f, "invalid denom {:?}", denom),
+      MultiSendError::NegativeBalance { address, denom, amount } =>
+        write!(f, "balance of token {} in address {} would become negative: {}", denom, address, amount),
+      MultiSendError::SupplyMismatch { denom, delta, burned } =>
+        write!(f, "supply invariant violated for token {}: changes add up to {} but {} was burned", denom, delta, burned),
+      MultiSendError::InvalidRate { denom, rate } => write!(f, "invalid rate {} for token {}", rate, denom),
+      MultiSendError::ZeroCommissionWeights { denom } => write!(f, "commission recipients of token {} all have a zero weight", denom),
+      MultiSendError::DuplicateDefinition { denom } => write!(f, "more than one denom definition for token {}", denom),
+    }
+  }
+}
+
+impl std::error::Error for MultiSendError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::amount;
+
+  #[test]
+  fn codes_are_stable_and_unique() {
+    let errors = [
+      MultiSendError::AmountMismatch(vec![DenomMismatch { denom: "denom1".to_string(), inputs: amount(350), outputs: amount(450) }]),
+      MultiSendError::InsufficientBalance { address: "account1".to_string(), denom: "denom1".to_string(), required: amount(385), available: amount(0) },
+      MultiSendError::UnknownDenom { denom: "denom9".to_string() },
+      MultiSendError::UnknownAccount { address: "account9".to_string() },
+      MultiSendError::Overflow { denom: "denom1".to_string() },
+      MultiSendError::NoInputs,
+      MultiSendError::NoOutputs,
+      MultiSendError::EmptyAddress,
+      MultiSendError::EmptyCoins { address: "account1".to_string() },
+      MultiSendError::InvalidAmount { address: "account1".to_string(), denom: "denom1".to_string(), amount: amount(0) },
+      MultiSendError::DuplicateDenom { address: "account1".to_string(), denom: "denom1".to_string() },
+      MultiSendError::InvalidDenom { denom: "d".to_string() },
+      MultiSendError::NegativeBalance { address: "account1".to_string(), denom: "denom1".to_string(), amount: SignedAmount::negative(amount(1)) },
+      MultiSendError::SupplyMismatch { denom: "denom1".to_string(), delta: SignedAmount::negative(amount(7)), burned: amount(8) },
+      MultiSendError::InvalidRate { denom: "denom1".to_string(), rate: Decimal::percent(-5) },
+      MultiSendError::ZeroCommissionWeights { denom: "denom1".to_string() },
+      MultiSendError::DuplicateDefinition { denom: "denom1".to_string() },
+    ];
+    let codes: Vec<u32> = errors.iter().map(|err| err.code()).collect();
+    assert_eq!(codes, vec![2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]);
+    assert!(errors.iter().all(|err| err.codespace() == "multisend"));
+  }
+
+  #[test]
+  fn display_includes_details() {
+    let err = MultiSendError::InsufficientBalance { address: "account1".to_string(), denom: "denom1".to_string(), required: amount(385), available: amount(100) };
+    assert_eq!(err.to_string(), "insufficient balance for token denom1 in address account1: required 385, available 100");
+
+    let err = MultiSendError::AmountMismatch(vec![
+      DenomMismatch { denom: "denom1".to_string(), inputs: amount(350), outputs: amount(450) },
+      DenomMismatch { denom: "denom9".to_string(), inputs: amount(0), outputs: amount(500) },
+    ]);
+    assert_eq!(err.to_string(), "input and output amounts mismatch: denom1 (inputs 350, outputs 450), denom9 (inputs 0, outputs 500)");
+  }
+}
@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+
+use crate::amount::ZERO;
+use crate::coins::Coins;
+use crate::error::MultiSendError;
+use crate::receipt::Receipt;
+use crate::{add_amount, debit_spent, MultiSend};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+  CoinSpent,
+  CoinReceived,
+  Transfer,
+  Burn,
+  Commission,
+}
+
+impl EventKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      EventKind::CoinSpent => "coin_spent",
+      EventKind::CoinReceived => "coin_received",
+      EventKind::Transfer => "transfer",
+      EventKind::Burn => "burn",
+      EventKind::Commission => "commission",
+    }
+  }
+}
+
+// A typed event with ordered key/value attributes, in the shape of cosmos sdk events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+  pub kind: EventKind,
+  pub attributes: Vec<(&'static str, String)>,
+}
+
+impl Event {
+  fn new(kind: EventKind) -> Event {
+    Event { kind, attributes: vec![] }
+  }
+
+  fn attr(mut self, key: &'static str, value: impl ToString) -> Event {
+    self.attributes.push((key, value.to_string()));
+    self
+  }
+
+  pub fn attribute(&self, key: &str) -> Option<&str> {
+    self.attributes.iter().find(|(k, _)| *k == key).map(|(_, value)| value.as_str())
+  }
+
+  // {"type":"burn","attributes":[{"key":"burner","value":"account1"},...]}
+  pub fn to_json(&self) -> String {
+    let mut json = format!("{{\"type\":{},\"attributes\":[", json_string(self.kind.as_str()));
+    for (i, (key, value)) in self.attributes.iter().enumerate() {
+      let separator = if i == 0 { "" } else { "," };
+      let _ = write!(json, "{}{{\"key\":{},\"value\":{}}}", separator, json_string(key), json_string(value));
+    }
+    json.push_str("]}");
+    json
+  }
+}
+
+fn json_string(value: &str) -> String {
+  let mut json = String::with_capacity(value.len() + 2);
+  json.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => json.push_str("\\\""),
+      '\\' => json.push_str("\\\\"),
+      '\n' => json.push_str("\\n"),
+      '\r' => json.push_str("\\r"),
+      '\t' => json.push_str("\\t"),
+      c if (c as u32) < 0x20 => { let _ = write!(json, "\\u{:04x}", c as u32); }
+      c => json.push(c),
+    }
+  }
+  json.push('"');
+  json
+}
+
+// Receives the events of every committed tx, in order. Implement it to forward events to an indexer.
+pub trait EventSink {
+  fn emit(&mut self, event: Event);
+}
+
+impl EventSink for Vec<Event> {
+  fn emit(&mut self, event: Event) {
+    self.push(event);
+  }
+}
+
+// Writes one JSON object per line. The first write error is kept and returned by `finish`; later events are dropped.
+pub struct JsonLinesSink<W: io::Write> {
+  writer: W,
+  error: Option<io::Error>,
+}
+
+impl<W: io::Write> JsonLinesSink<W> {
+  pub fn new(writer: W) -> JsonLinesSink<W> {
+    JsonLinesSink { writer, error: None }
+  }
+
+  pub fn finish(mut self) -> io::Result<W> {
+    match self.error.take() {
+      Some(err) => Err(err),
+      None => self.writer.flush().map(|_| self.writer),
+    }
+  }
+}
+
+impl<W: io::Write> EventSink for JsonLinesSink<W> {
+  fn emit(&mut self, event: Event) {
+    if self.error.is_none() {
+      self.error = writeln!(self.writer, "{}", event.to_json()).err();
+    }
+  }
+}
+
+// Events of a tx, mapped 1:1 onto its balance changes, in this order:
+//  - coin_spent per input address with everything it paid (principal + burn + commission, the fees possibly in a
+//    separate fee denom)
+//  - coin_received and transfer per output address with the amount it receives
+//  - burn per input address and denom with a non-zero burn share, in the fee denom
+//  - commission per input address and denom with a non-zero commission share, in the fee denom, naming the
+//    recipients credited with commission on transfers of that denom
+//  - coin_received per commission recipient with its total commission credit
+// Burn and commission events carry the fee denom as `denom` and the denom whose transfer they are charged on as
+// `transferred_denom`; the two differ when the fees are paid in a separate fee denom.
+// Within each group addresses and denoms are sorted, so the same tx always yields the same events.
+pub fn multi_send_events(multi_send_tx: &MultiSend, receipt: &Receipt) -> Result<Vec<Event>, MultiSendError> {
+  let mut events = vec![];
+
+  let mut gross_per_address: BTreeMap<&str, Coins> = BTreeMap::new();
+  for debit in &receipt.debits {
+    for (denom, amount) in debit_spent(debit)? {
+      add_amount(gross_per_address.entry(&debit.address).or_default(), denom, amount)?;
+    }
+  }
+  for (address, gross) in &gross_per_address {
+    events.push(Event::new(EventKind::CoinSpent).attr("spender", address).attr("amount", gross));
+  }
+
+  for (address, coins) in &multi_send_tx.get_outputs_amounts_per_address()? {
+    events.push(Event::new(EventKind::CoinReceived).attr("receiver", address).attr("amount", coins));
+    events.push(Event::new(EventKind::Transfer).attr("recipient", address).attr("amount", coins));
+  }
+
+  for debit in receipt.debits.iter().filter(|debit| debit.burn != ZERO) {
+    events.push(Event::new(EventKind::Burn)
+      .attr("burner", &debit.address)
+      .attr("amount", format!("{}{}", debit.burn, debit.fee_denom))
+      .attr("denom", &debit.fee_denom)
+      .attr("transferred_denom", &debit.denom));
+  }
+
+  for debit in receipt.debits.iter().filter(|debit| debit.commission != ZERO) {
+    let recipients: Vec<&str> = receipt.commission_credits.iter()
+      .filter(|credit| credit.denom == debit.denom)
+      .map(|credit| credit.recipient.as_str())
+      .collect();
+    events.push(Event::new(EventKind::Commission)
+      .attr("spender", &debit.address)
+      .attr("receiver", recipients.join(","))
+      .attr("amount", format!("{}{}", debit.commission, debit.fee_denom))
+      .attr("denom", &debit.fee_denom)
+      .attr("transferred_denom", &debit.denom));
+  }
+
+  let mut credited_per_recipient: BTreeMap<&str, Coins> = BTreeMap::new();
+  for credit in &receipt.commission_credits {
+    add_amount(credited_per_recipient.entry(&credit.recipient).or_default(), &credit.fee_denom, credit.amount)?;
+  }
+  for (recipient, coins) in &credited_per_recipient {
+    events.push(Event::new(EventKind::CoinReceived).attr("receiver", recipient).attr("amount", coins));
+  }
+
+  Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::amount::{Amount, SignedAmount, ZERO};
+  use crate::bank::Bank;
+  use crate::coins::SignedCoins;
+  use crate::decimal::Decimal;
+  use crate::testing::{amount, balance, bank};
+  use crate::DenomDefinition;
+
+  fn summary(events: &[Event]) -> Vec<String> {
+    events.iter()
+      .map(|event| {
+        let attributes: Vec<String> = event.attributes.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        format!("{} {}", event.kind.as_str(), attributes.join(" "))
+      })
+      .collect()
+  }
+
+  #[test]
+  fn emits_ordered_events_for_a_committed_tx() {
+    let mut bank = bank();
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "500denom1,100denom2")],
+      vec![balance("account_recipient", "500denom1,100denom2")],
+    ).unwrap();
+
+    let mut events = vec![];
+    bank.execute_with_events(multi_send, &mut events).unwrap();
+    assert_eq!(summary(&events), vec![
+      "coin_spent spender=account1 amount=600denom1,100denom2",
+      "coin_received receiver=account_recipient amount=500denom1,100denom2",
+      "transfer recipient=account_recipient amount=500denom1,100denom2",
+      "burn burner=account1 amount=40denom1 denom=denom1 transferred_denom=denom1",
+      "commission spender=account1 receiver=issuer_account_A amount=60denom1 denom=denom1 transferred_denom=denom1",
+      "coin_received receiver=issuer_account_A amount=60denom1",
+    ]);
+  }
+
+  #[test]
+  fn received_minus_spent_equals_balance_changes() {
+    let mut bank = bank();
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "300denom1"), balance("account2", "200denom1")],
+      vec![balance("account_recipient", "350denom1"), balance("account2", "150denom1")],
+    ).unwrap();
+
+    let mut events = vec![];
+    let receipt = bank.execute_with_events(multi_send, &mut events).unwrap();
+
+    let mut net: BTreeMap<String, SignedCoins> = BTreeMap::new();
+    for event in &events {
+      let (address, sign): (&str, fn(Amount) -> SignedAmount) = match event.kind {
+        EventKind::CoinSpent => (event.attribute("spender").unwrap(), SignedAmount::negative),
+        EventKind::CoinReceived => (event.attribute("receiver").unwrap(), SignedAmount::positive),
+        _ => continue,
+      };
+      let amount: Coins = event.attribute("amount").unwrap().parse().unwrap();
+      for coin in &amount {
+        net.entry(address.to_string()).or_default().add_amount(&coin.denom, sign(coin.amount)).unwrap();
+      }
+    }
+    let net: Vec<(String, SignedCoins)> = net.into_iter().filter(|(_, coins)| !coins.is_empty()).collect();
+    let changes: Vec<(String, SignedCoins)> = receipt.changes.into_iter().map(|change| (change.address, change.coins)).collect();
+    assert_eq!(net, changes);
+
+    // burned coins only show up in burn events
+    let burned = events.iter()
+      .filter(|event| event.kind == EventKind::Burn)
+      .map(|event| event.attribute("amount").unwrap().parse::<crate::Coin>().unwrap().amount)
+      .try_fold(ZERO, |burned, amount| burned.checked_add(amount))
+      .unwrap();
+    assert_eq!(burned, receipt.burned.amount_of("denom1"));
+  }
+
+  #[test]
+  fn commission_events_name_every_recipient() {
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::percent(10)).with_commission_recipients(&[("treasury", 1), ("referrer", 1)])
+    ];
+    let mut bank = Bank::new(vec![balance("account1", "1000denom1")], definitions).unwrap();
+    let multi_send = MultiSend::new(vec![balance("account1", "100denom1")], vec![balance("account_recipient", "100denom1")]).unwrap();
+
+    let mut events = vec![];
+    bank.execute_with_events(multi_send, &mut events).unwrap();
+    assert_eq!(summary(&events)[3..], [
+      "commission spender=account1 receiver=referrer,treasury amount=10denom1 denom=denom1 transferred_denom=denom1",
+      "coin_received receiver=referrer amount=5denom1",
+      "coin_received receiver=treasury amount=5denom1",
+    ]);
+  }
+
+  #[test]
+  fn commission_events_keep_denoms_sharing_a_fee_denom_apart() {
+    // 1% commission on usdt and on euro, both paid in core to their own issuers
+    let definitions = vec![
+      DenomDefinition::new("usdt", "issuer_usdt", Decimal::zero(), Decimal::percent(1)).with_fee_denom("core", Decimal::one()),
+      DenomDefinition::new("euro", "issuer_euro", Decimal::zero(), Decimal::percent(1)).with_fee_denom("core", Decimal::one()),
+      DenomDefinition::new("core", "issuer_core", Decimal::zero(), Decimal::zero()),
+    ];
+    let mut bank = Bank::new(vec![balance("account1", "15core,500euro,1000usdt")], definitions).unwrap();
+    let multi_send = MultiSend::new(vec![balance("account1", "500euro,1000usdt")], vec![balance("account_recipient", "500euro,1000usdt")]).unwrap();
+
+    let mut events = vec![];
+    bank.execute_with_events(multi_send, &mut events).unwrap();
+    assert_eq!(summary(&events)[3..], [
+      "commission spender=account1 receiver=issuer_euro amount=5core denom=core transferred_denom=euro",
+      "commission spender=account1 receiver=issuer_usdt amount=10core denom=core transferred_denom=usdt",
+      "coin_received receiver=issuer_euro amount=5core",
+      "coin_received receiver=issuer_usdt amount=10core",
+    ]);
+    // the bank counts commission in the denom it is paid in
+    assert_eq!((bank.commission_of("usdt"), bank.commission_of("core")), (ZERO, amount(15)));
+  }
+
+  #[test]
+  fn rejected_tx_emits_nothing() {
+    let mut bank = bank();
+    let multi_send = MultiSend::new(vec![balance("account2", "500denom1")], vec![balance("account1", "500denom1")]).unwrap();
+
+    let mut events = vec![];
+    assert!(bank.execute_with_events(multi_send, &mut events).is_err());
+    assert!(events.is_empty());
+  }
+
+  #[test]
+  fn json_lines_sink_writes_one_escaped_object_per_line() {
+    let mut sink = JsonLinesSink::new(Vec::new());
+    sink.emit(Event::new(EventKind::Transfer).attr("recipient", "a\"b\\c\n").attr("amount", "5denom1"));
+    sink.emit(Event::new(EventKind::Burn).attr("burner", "account1"));
+
+    let output = String::from_utf8(sink.finish().unwrap()).unwrap();
+    assert_eq!(output, concat!(
+      "{\"type\":\"transfer\",\"attributes\":[{\"key\":\"recipient\",\"value\":\"a\\\"b\\\\c\\n\"},{\"key\":\"amount\",\"value\":\"5denom1\"}]}\n",
+      "{\"type\":\"burn\",\"attributes\":[{\"key\":\"burner\",\"value\":\"account1\"}]}\n",
+    ));
+  }
+}
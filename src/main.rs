@@ -1,5 +1,23 @@
 #![allow(dead_code)]
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+mod amount;
+mod bank;
+mod coins;
+mod decimal;
+mod error;
+mod events;
+mod receipt;
+#[cfg(test)]
+mod testing;
+mod uint256;
+
+use amount::{Amount, SignedAmount, ZERO};
+use coins::{is_valid_denom, Coin, Coins, CoinsError, SignedCoins};
+use decimal::{Decimal, Rounding};
+use error::{DenomMismatch, MultiSendError};
+use receipt::{CommissionCredit, FeeDebit, Receipt};
 
 fn main() {}
 // A user can submit a `MultiSend` transaction (similar to bank.MultiSend in cosmos sdk) to transfer multiple
@@ -7,6 +25,7 @@ fn main() {}
 // for a coin type, e.g USDT and USDC can be considered different denoms; in cosmos ecosystem they are called
 // denoms, in ethereum world they are called symbols.
 // The sum of input coins and output coins must match for every transaction.
+#[derive(Debug)]
 struct MultiSend {
     // inputs contain the list of accounts that want to send coins from, and how many coins from each account we want to send.
     inputs: Vec<Balance>,
@@ -16,118 +35,243 @@ struct MultiSend {
 }
 
 impl MultiSend {
-  fn get_inputs_amounts_per_token(&self) -> HashMap<String, i128> { MultiSend::sum_amounts(&self.inputs) }
-  fn get_output_amounts_per_token(&self) -> HashMap<String, i128> { MultiSend::sum_amounts(&self.outputs) }
-  fn assert_input_output_amounts_should_same(&self) -> Result<(), String> {
-    let input_amounts = self.get_inputs_amounts_per_token();
-    let output_amounts = self.get_output_amounts_per_token();
-    for (denom, value) in input_amounts.iter() {
-      let output_value = output_amounts.get(denom);
-      if output_value != Some(value) {
-        return Err(format!("Input and Output token amount mismatches for token: {}", denom));
-      }
+  // Builds a tx that already passed `validate_basic`.
+  fn new(inputs: Vec<Balance>, outputs: Vec<Balance>) -> Result<MultiSend, MultiSendError> {
+    let multi_send = MultiSend { inputs, outputs };
+    multi_send.validate_basic()?;
+    Ok(multi_send)
+  }
+
+  // Stateless checks, similar to `MsgMultiSend.ValidateBasic` in cosmos sdk. They run before any balance math.
+  fn validate_basic(&self) -> Result<(), MultiSendError> {
+    if self.inputs.is_empty() {
+      return Err(MultiSendError::NoInputs);
+    }
+    if self.outputs.is_empty() {
+      return Err(MultiSendError::NoOutputs);
+    }
+    self.inputs.iter().chain(self.outputs.iter()).try_for_each(Balance::validate_basic)
+  }
+
+  fn get_inputs_amounts_per_token(&self) -> Result<Coins, MultiSendError> { MultiSend::sum_amounts(&self.inputs) }
+  fn get_output_amounts_per_token(&self) -> Result<Coins, MultiSendError> { MultiSend::sum_amounts(&self.outputs) }
+  // Compares the totals of every denom that appears on either side, so an output-only denom cannot mint coins.
+  fn assert_input_output_amounts_should_same(&self) -> Result<(), MultiSendError> {
+    let input_amounts = self.get_inputs_amounts_per_token()?;
+    let output_amounts = self.get_output_amounts_per_token()?;
+    let denoms: BTreeSet<&String> = input_amounts.iter().chain(output_amounts.iter()).map(|coin| &coin.denom).collect();
+    let mismatches: Vec<DenomMismatch> = denoms.into_iter()
+      .map(|denom| DenomMismatch {
+        denom: denom.clone(),
+        inputs: input_amounts.amount_of(denom),
+        outputs: output_amounts.amount_of(denom),
+      })
+      .filter(|mismatch| mismatch.inputs != mismatch.outputs)
+      .collect();
+    if !mismatches.is_empty() {
+      return Err(MultiSendError::AmountMismatch(mismatches));
     }
     Ok(())
   }
   
-  fn get_nonissuer_input_output_amounts(&self, token_map: &HashMap<String, DenomDefinition>) -> (HashMap<String, i128>, HashMap<String, i128>) { 
-    let input_amounts = MultiSend::sum_amounts_non_issuer(&self.inputs, token_map);
-    let output_amounts = MultiSend::sum_amounts_non_issuer(&self.outputs, token_map);
-    (input_amounts, output_amounts)
+  // Collects, once per tx, the non-issuer amounts that every account's fee share depends on.
+  fn fee_context(&self, token_map: &BTreeMap<String, DenomDefinition>) -> Result<FeeContext, MultiSendError> {
+    let mut non_issuer_inputs: BTreeMap<String, Coins> = BTreeMap::new();
+    for balance in &self.inputs {
+      for coin in &balance.coins {
+        if token_map.get(&coin.denom).is_some_and(|token_def| token_def.is_fee_exempt(&balance.address)) { continue; }
+        add_amount(non_issuer_inputs.entry(balance.address.clone()).or_default(), &coin.denom, coin.amount)?;
+      }
+    }
+    let mut fee_context = FeeContext {
+      non_issuer_input_sum: MultiSend::sum_amounts_non_issuer(&self.inputs, token_map)?,
+      non_issuer_output_sum: MultiSend::sum_amounts_non_issuer(&self.outputs, token_map)?,
+      non_issuer_inputs,
+      tx_shares: BTreeMap::new(),
+    };
+    let tx_shares = fee_context.non_issuer_input_sum.iter()
+      .filter_map(|coin| token_map.get(&coin.denom))
+      .filter(|token_def| token_def.has_tx_wide_shares())
+      .map(|token_def| Ok((token_def.denom.clone(), token_def.tx_shares(&fee_context)?)))
+      .collect::<Result<_, MultiSendError>>()?;
+    fee_context.tx_shares = tx_shares;
+    Ok(fee_context)
   }
 
-  fn get_inputs_amounts_per_address(&self) -> HashMap<String, HashMap<String, i128>> { self.get_amounts_per_address(&self.inputs) }
-  fn get_outputs_amounts_per_address(&self) -> HashMap<String, HashMap<String, i128>> { self.get_amounts_per_address(&self.outputs) }
-  fn get_amounts_per_address(&self, balances: &Vec<Balance>) -> HashMap<String, HashMap<String, i128>> { 
-    let mut amounts_per_address: HashMap<String, HashMap<String, i128>> = HashMap::new();
+  fn get_inputs_amounts_per_address(&self) -> Result<BTreeMap<String, Coins>, MultiSendError> { self.get_amounts_per_address(&self.inputs) }
+  fn get_outputs_amounts_per_address(&self) -> Result<BTreeMap<String, Coins>, MultiSendError> { self.get_amounts_per_address(&self.outputs) }
+  fn get_amounts_per_address(&self, balances: &[Balance]) -> Result<BTreeMap<String, Coins>, MultiSendError> {
+    let mut amounts_per_address: BTreeMap<String, Coins> = BTreeMap::new();
     for balance in balances {
+      let coins = amounts_per_address.entry(balance.address.clone()).or_default();
       for coin in &balance.coins {
-        if let Some(coins) = amounts_per_address.get_mut(&balance.address) {
-          if let Some(coin_amount) = coins.get_mut(&coin.denom) {
-            *coin_amount = *coin_amount + coin.amount;
-          } else {
-            coins.insert(coin.denom.clone(), coin.amount);
-          }
-        } else {
-          let mut new_coins = HashMap::new();
-          new_coins.insert(coin.denom.clone(), coin.amount);
-          amounts_per_address.insert(balance.address.clone(), new_coins);
-        }
+        add_amount(coins, &coin.denom, coin.amount)?;
       }
     }
-    amounts_per_address
+    Ok(amounts_per_address)
   }
   
-  fn assert_balances_should_bigger_than_input(&self, account_map: &HashMap<String, Vec<Coin>>, token_map: &HashMap<String, DenomDefinition>) -> Result<(), String> {
-    let amounts_per_account = self.get_inputs_amounts_per_address();
-    let (non_issuer_input_sum, non_issuer_output_sum) = self.get_nonissuer_input_output_amounts(token_map);
+  fn assert_balances_should_bigger_than_input(&self, account_map: &BTreeMap<String, Coins>, token_map: &BTreeMap<String, DenomDefinition>, fee_context: &FeeContext) -> Result<(), MultiSendError> {
+    let amounts_per_account = self.get_inputs_amounts_per_address()?;
     
-    for (address, account_token_map) in amounts_per_account.iter() {
-      let account_coins = account_map.get(address);
-      if account_coins.is_none() {
-        return Err(format!("Address not found in original balances {}", address));
-      }
-      for (denom, value) in account_token_map.iter() {
-        let token_denom = token_map.get(denom).expect("Invalid Token Found");
-        let res = account_coins.unwrap().iter().find(|&coin| coin.denom == *denom 
-          && coin.amount >= token_denom.calculated_amount(*value, &non_issuer_input_sum, &non_issuer_output_sum));
-        if res.is_none() {
-          return Err(format!("Insufficient balance for token: {} in address: {}", denom, address));
+    for (address, account_coins_to_send) in amounts_per_account.iter() {
+      let account_coins = account_map.get(address)
+        .ok_or_else(|| MultiSendError::UnknownAccount { address: address.clone() })?;
+      // fees paid in another denom add to what the account sends of that denom
+      let mut required_coins = Coins::default();
+      for coin in account_coins_to_send {
+        let debit = DenomDefinition::lookup(token_map, &coin.denom)?.debit(address, coin.amount, fee_context)?;
+        for (denom, amount) in debit_spent(&debit)? {
+          add_amount(&mut required_coins, denom, amount)?;
         }
       }
+      if let Err(short) = account_coins.safe_sub(&required_coins) {
+        let available = account_coins.amount_of(&short.denom);
+        return Err(MultiSendError::InsufficientBalance { address: address.clone(), denom: short.denom, required: short.amount, available });
+      }
     }
 
     Ok(())
   }
 
-  fn sum_amounts(balances: &Vec<Balance>) -> HashMap<String, i128> {
-    let mut amounts_per_coin: HashMap<String, i128> = HashMap::new();
-    for balance in balances {
-      for coin in &balance.coins {
-        if let Some(coin_amount) = amounts_per_coin.get_mut(&coin.denom) {
-          *coin_amount = *coin_amount + coin.amount;
-        } else {
-          amounts_per_coin.insert(coin.denom.clone(), coin.amount);
-        }
+  // One debit per input address and denom, sorted by address and then by denom: what each sender pays for its
+  // inputs, fees included.
+  fn fee_debits(&self, token_map: &BTreeMap<String, DenomDefinition>, fee_context: &FeeContext) -> Result<Vec<FeeDebit>, MultiSendError> {
+    let mut debits = vec![];
+    for (address, account_coins) in &self.get_inputs_amounts_per_address()? {
+      for coin in account_coins {
+        debits.push(DenomDefinition::lookup(token_map, &coin.denom)?.debit(address, coin.amount, fee_context)?);
       }
     }
-    amounts_per_coin
+    Ok(debits)
+  }
+
+  // The debits of a well-formed tx, without looking at any balance.
+  fn required_debits(&self, token_map: &BTreeMap<String, DenomDefinition>) -> Result<Vec<FeeDebit>, MultiSendError> {
+    self.validate_basic()?;
+    self.assert_input_output_amounts_should_same()?;
+    let fee_context = self.fee_context(token_map)?;
+    self.fee_debits(token_map, &fee_context)
+  }
+
+  fn sum_amounts(balances: &[Balance]) -> Result<Coins, MultiSendError> {
+    let mut amounts_per_coin = Coins::default();
+    for coin in balances.iter().flat_map(|balance| balance.coins.iter()) {
+      add_amount(&mut amounts_per_coin, &coin.denom, coin.amount)?;
+    }
+    Ok(amounts_per_coin)
   }
 
-  fn sum_amounts_non_issuer(balances: &Vec<Balance>, token_map: &HashMap<String, DenomDefinition>) -> HashMap<String, i128> {
+  fn sum_amounts_non_issuer(balances: &[Balance], token_map: &BTreeMap<String, DenomDefinition>) -> Result<Coins, MultiSendError> {
     
-    // sum amounts by skipping issuer's coin
-    let mut amounts_per_coin: HashMap<String, i128> = HashMap::new();
+    // sum amounts by skipping the coins of the issuer and the other fee-exempt addresses
+    let mut amounts_per_coin = Coins::default();
     for balance in balances {
       for coin in &balance.coins {
-        // skip issuer
+        // skip the issuer and exempt addresses
         if let Some(token_def) = token_map.get(&coin.denom) {
-          if token_def.issuer == balance.address { continue; }
-        }
-        if let Some(coin_amount) = amounts_per_coin.get_mut(&coin.denom) {
-          *coin_amount = *coin_amount + coin.amount;
-        } else {
-          amounts_per_coin.insert(coin.denom.clone(), coin.amount);
+          if token_def.is_fee_exempt(&balance.address) { continue; }
         }
+        add_amount(&mut amounts_per_coin, &coin.denom, coin.amount)?;
       }
     }
-    amounts_per_coin
+    Ok(amounts_per_coin)
   }
 
 }
-#[derive(Debug)]
-pub struct Coin {
-    pub denom: String,
-    pub amount: i128,
+
+// The amounts of a tx, excluding the fee-exempt issuers, that the fee shares of its accounts depend on.
+#[derive(Debug, Default)]
+struct FeeContext {
+  // per denom, the sum of the inputs and the sum of the outputs of accounts that are not the issuer
+  non_issuer_input_sum: Coins,
+  non_issuer_output_sum: Coins,
+  // the same inputs per address, which a denom's tx-wide fee totals are spread over
+  non_issuer_inputs: BTreeMap<String, Coins>,
+  // per denom whose shares depend on every sender of the tx (largest-remainder rounding or tx-wide fee limits), the
+  // final (burn, commission) share of each of its non-issuer senders in the fee denom, computed once for the whole tx
+  tx_shares: BTreeMap<String, BTreeMap<String, (Amount, Amount)>>,
+}
+
+impl FeeContext {
+  // the (burn, commission) share of `address` in the tx-wide fees of `denom`, zero if it sends none of it
+  fn tx_share(&self, denom: &str, address: &str) -> (Amount, Amount) {
+    self.tx_shares.get(denom).and_then(|shares| shares.get(address)).copied().unwrap_or((ZERO, ZERO))
+  }
+}
+
+// adds to `coins` in place, turning an overflow into a rejection
+fn add_amount(coins: &mut Coins, denom: &str, amount: Amount) -> Result<(), MultiSendError> {
+  coins.add_amount(denom, amount).ok_or_else(|| MultiSendError::Overflow { denom: denom.to_string() })
+}
+
+// the coins that left the account of `debit`, turning an overflow into a rejection
+fn debit_spent(debit: &FeeDebit) -> Result<Vec<(&str, Amount)>, MultiSendError> {
+  debit.spent().ok_or_else(|| MultiSendError::Overflow { denom: debit.fee_denom.clone() })
+}
+
+// adds a signed change to `changes` in place, turning an overflow into a rejection
+fn add_change(changes: &mut SignedCoins, denom: &str, change: SignedAmount) -> Result<(), MultiSendError> {
+  changes.add_amount(denom, change).ok_or_else(|| MultiSendError::Overflow { denom: denom.to_string() })
 }
 
 #[derive(Debug)]
 struct Balance {
     address: String,
-    coins: Vec<Coin>,
+    coins: Coins,
+}
+
+// "address:coins", e.g. "account1:1200denom1,2000denom2"
+impl fmt::Display for Balance {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.address, self.coins)
+  }
+}
+
+// The net change of one address caused by a tx (negative means deduction, positive means addition). It has its
+// own type so a change can never be mistaken for a balance or a transfer amount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BalanceChange {
+    address: String,
+    coins: SignedCoins,
+}
+
+// "address:coins", e.g. "account1:-1200denom1,-2000denom2"
+impl fmt::Display for BalanceChange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.address, self.coins)
+  }
+}
+
+impl Balance {
+  // Builds a balance from an unsorted coin list, rejecting duplicate denoms and zero amounts.
+  fn new(address: &str, coins: Vec<Coin>) -> Result<Balance, MultiSendError> {
+    let coins = Coins::new(coins).map_err(|err| match err {
+      CoinsError::DuplicateDenom(denom) => MultiSendError::DuplicateDenom { address: address.to_string(), denom },
+      CoinsError::ZeroAmount(denom) => MultiSendError::InvalidAmount { address: address.to_string(), denom, amount: ZERO },
+    })?;
+    Ok(Balance { address: address.to_string(), coins })
+  }
+
+  // `Coins` already rules out duplicate denoms and zero amounts
+  fn validate_basic(&self) -> Result<(), MultiSendError> {
+    if self.address.is_empty() {
+      return Err(MultiSendError::EmptyAddress);
+    }
+    if self.coins.is_empty() {
+      return Err(MultiSendError::EmptyCoins { address: self.address.clone() });
+    }
+    for coin in &self.coins {
+      if !is_valid_denom(&coin.denom) {
+        return Err(MultiSendError::InvalidDenom { denom: coin.denom.clone() });
+      }
+    }
+    Ok(())
+  }
 }
 
 // A Denom has a definition (`CoinDefinition`) which contains different attributes related to the denom:
+#[derive(Debug, Clone)]
 struct DenomDefinition {
     // the unique identifier for the token (e.g `core`, `eth`, `usdt`, etc.)
     denom: String,
@@ -139,10 +283,85 @@ struct DenomDefinition {
     // rounding it up to an integer value. For example if an account sends 100 token and burn_rate is
     // 0.2, then 120 (100 + 100 * 0.2) will be deducted from sender account and 100 will be deposited to the recipient
     // account (i.e 20 tokens will be burnt)
-    burn_rate: f64,
+    // Rates are fixed-point decimals so the fee math is exact and deterministic across machines.
+    burn_rate: Decimal,
     // commission_rate is exactly same as the burn_rate, but the calculated value will be transferred to the
     // issuer's account address instead of being burnt.
-    commission_rate: f64,
+    commission_rate: Decimal,
+    // optional higher tiers of the burn and commission rates, sorted by threshold. The rate of a tx is the one of the
+    // highest tier whose threshold the denom's non-issuer transferred amount reaches, and `burn_rate` or
+    // `commission_rate` below the first threshold; without tiers the rate is flat. The rate applies to the whole
+    // amount, not only to the part above the threshold, so a lower rate makes the fee drop at the threshold: with 2%
+    // below 1000 and 1% from 1000, sending 999 costs 1019 while sending 1000 costs 1010.
+    burn_tiers: Vec<FeeTier>,
+    commission_tiers: Vec<FeeTier>,
+    // addresses that, like the issuer, send and receive the denom without fees, e.g. exchanges and bridge escrows.
+    // Their coins count neither towards the non-issuer sums nor as a sender's share; commission still goes to `issuer`.
+    exempt_addresses: BTreeSet<String>,
+    // (recipient, weight) pairs that share the commission of a tx in proportion to their weights, e.g. the issuer's
+    // treasury, a community pool and a referrer. Without recipients the whole commission goes to `issuer`.
+    commission_recipients: Vec<(String, u64)>,
+    // the denom the burn and commission are paid in, and how many of its units one unit of this denom's fee costs.
+    // Fees are computed in this denom (rates, tiers, limits and rounding included) and then converted, rounding up;
+    // shares that depend on the whole tx are converted as a total and spread over the senders again. Without a fee
+    // denom they are paid in this denom itself.
+    fee_denom: Option<(String, Decimal)>,
+    // how each account's burn and commission share is rounded to an integer; rounding up by default
+    rounding: RoundingMode,
+    // optional bounds on the burn and on the commission, e.g. "1% but at most 10000 units"; `fee_limit_scope` says
+    // whether they bound every account's share or the denom's total in a tx. A maximum wins over a minimum above it.
+    min_burn: Option<Amount>,
+    max_burn: Option<Amount>,
+    min_commission: Option<Amount>,
+    max_commission: Option<Amount>,
+    fee_limit_scope: FeeLimitScope,
+}
+
+// The rate that applies to the whole tx once the non-issuer transferred amount reaches `from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FeeTier {
+  from: Amount,
+  rate: Decimal,
+}
+
+// What the fee minimums and maximums of a denom apply to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FeeLimitScope {
+  // every sender's share is bounded on its own
+  #[default]
+  PerAccount,
+  // the denom's total in a tx is bounded, and a bounded total is spread over the senders by largest remainder
+  PerTx,
+}
+
+// How the per-account burn and commission shares of a denom are rounded to whole units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum RoundingMode {
+  // round every share up, so the senders pay at least the exact fee (the original behaviour)
+  #[default]
+  Ceil,
+  // round every share down, so the senders never pay more than the exact fee
+  Floor,
+  // round every share to the nearest unit, ties to even (banker's rounding)
+  HalfEven,
+  // round the burn share down and charge the rest of the rounded up total fee, ceil(burn + commission), as the
+  // commission share: the burn never exceeds the exact amount, and the total is never more than with `Ceil` but can
+  // be one unit less (10% burn and 3% commission of 45 cost 51 instead of 52). What the rounding cut off is paid as
+  // commission, so it goes to the issuer, or is split among `commission_recipients` with the rest of the commission.
+  FloorRemainderToIssuer,
+  // round the denom's total burn and total commission up once, and spread each total over the senders by largest
+  // remainder, so the shares add up to exactly the rounded total however many senders there are
+  LargestRemainder,
+}
+
+impl RoundingMode {
+  fn burn_rounding(&self) -> Rounding {
+    match self {
+      RoundingMode::Ceil | RoundingMode::LargestRemainder => Rounding::Ceil,
+      RoundingMode::Floor | RoundingMode::FloorRemainderToIssuer => Rounding::Floor,
+      RoundingMode::HalfEven => Rounding::HalfEven,
+    }
+  }
 }
 
 // Implement `calculate_balance_changes` with the following requirements.
@@ -179,25 +398,332 @@ struct DenomDefinition {
 //   There are examples in README.md, you can convert them into tests, but you should add more cases.
 
 impl DenomDefinition {
-  fn calculated_amount(&self, amount: i128, non_issuer_input_sum: &HashMap<String, i128>, non_issuer_output_sum: &HashMap<String, i128>) -> i128 {
-    let non_issuer_input = non_issuer_input_sum.get(&self.denom).expect("Coin Not Found");
-    let non_issuer_output = non_issuer_output_sum.get(&self.denom).expect("Coin Not Found");
-    amount + self.burn_amount(amount, *non_issuer_input, *non_issuer_output) + self.commission_amount(amount, non_issuer_input_sum, non_issuer_output_sum)
+  fn new(denom: &str, issuer: &str, burn_rate: Decimal, commission_rate: Decimal) -> DenomDefinition {
+    DenomDefinition {
+      denom: denom.to_string(),
+      issuer: issuer.to_string(),
+      burn_rate,
+      commission_rate,
+      burn_tiers: vec![],
+      commission_tiers: vec![],
+      exempt_addresses: BTreeSet::new(),
+      commission_recipients: vec![],
+      fee_denom: None,
+      rounding: RoundingMode::default(),
+      min_burn: None,
+      max_burn: None,
+      min_commission: None,
+      max_commission: None,
+      fee_limit_scope: FeeLimitScope::default(),
+    }
+  }
+
+  fn with_rounding(mut self, rounding: RoundingMode) -> DenomDefinition {
+    self.rounding = rounding;
+    self
+  }
+
+  fn with_burn_tiers(mut self, mut tiers: Vec<FeeTier>) -> DenomDefinition {
+    tiers.sort_by_key(|tier| tier.from);
+    self.burn_tiers = tiers;
+    self
+  }
+
+  fn with_commission_tiers(mut self, mut tiers: Vec<FeeTier>) -> DenomDefinition {
+    tiers.sort_by_key(|tier| tier.from);
+    self.commission_tiers = tiers;
+    self
+  }
+
+  fn with_exempt_addresses(mut self, addresses: &[&str]) -> DenomDefinition {
+    self.exempt_addresses.extend(addresses.iter().map(|address| address.to_string()));
+    self
+  }
+
+  fn with_commission_recipients(mut self, recipients: &[(&str, u64)]) -> DenomDefinition {
+    self.commission_recipients = recipients.iter().map(|(recipient, weight)| (recipient.to_string(), *weight)).collect();
+    self
+  }
+
+  fn with_fee_denom(mut self, fee_denom: &str, conversion_rate: Decimal) -> DenomDefinition {
+    self.fee_denom = Some((fee_denom.to_string(), conversion_rate));
+    self
+  }
+
+  fn with_min_burn(mut self, min_burn: Amount) -> DenomDefinition {
+    self.min_burn = Some(min_burn);
+    self
+  }
+
+  fn with_max_burn(mut self, max_burn: Amount) -> DenomDefinition {
+    self.max_burn = Some(max_burn);
+    self
+  }
+
+  fn with_min_commission(mut self, min_commission: Amount) -> DenomDefinition {
+    self.min_commission = Some(min_commission);
+    self
+  }
+
+  fn with_max_commission(mut self, max_commission: Amount) -> DenomDefinition {
+    self.max_commission = Some(max_commission);
+    self
+  }
+
+  fn with_fee_limit_scope(mut self, fee_limit_scope: FeeLimitScope) -> DenomDefinition {
+    self.fee_limit_scope = fee_limit_scope;
+    self
+  }
+
+  // Rates are only accepted in [0, 1], like in the cosmos sdk; a fee conversion rate has to be positive, or fees would
+  // be free, and the fee denom a valid denom. The issuer and commission recipients need an address, and recipients
+  // need a positive total weight; a single recipient with a zero weight is fine and never receives anything.
+  fn validate(&self) -> Result<(), MultiSendError> {
+    if self.issuer.is_empty() || self.commission_recipients.iter().any(|(recipient, _)| recipient.is_empty()) {
+      return Err(MultiSendError::EmptyAddress);
+    }
+    if !self.commission_recipients.is_empty() && self.commission_recipients.iter().all(|(_, weight)| *weight == 0) {
+      return Err(MultiSendError::ZeroCommissionWeights { denom: self.denom.clone() });
+    }
+    let tier_rates = self.burn_tiers.iter().chain(self.commission_tiers.iter()).map(|tier| tier.rate);
+    for rate in [self.burn_rate, self.commission_rate].into_iter().chain(tier_rates) {
+      if rate.is_negative() || rate > Decimal::one() {
+        return Err(MultiSendError::InvalidRate { denom: self.denom.clone(), rate });
+      }
+    }
+    match &self.fee_denom {
+      Some((fee_denom, _)) if !is_valid_denom(fee_denom) => Err(MultiSendError::InvalidDenom { denom: fee_denom.clone() }),
+      Some((_, rate)) if *rate <= Decimal::zero() => Err(MultiSendError::InvalidRate { denom: self.denom.clone(), rate: *rate }),
+      _ => Ok(()),
+    }
+  }
+
+  // Validates `definitions` and keys them by denom; a denom may only be defined once.
+  fn token_map(definitions: impl IntoIterator<Item = DenomDefinition>) -> Result<BTreeMap<String, DenomDefinition>, MultiSendError> {
+    let mut token_map = BTreeMap::new();
+    for def in definitions {
+      def.validate()?;
+      if token_map.contains_key(&def.denom) {
+        return Err(MultiSendError::DuplicateDefinition { denom: def.denom });
+      }
+      token_map.insert(def.denom.clone(), def);
+    }
+    Ok(token_map)
+  }
+
+  fn lookup<'a>(token_map: &'a BTreeMap<String, DenomDefinition>, denom: &str) -> Result<&'a DenomDefinition, MultiSendError> {
+    token_map.get(denom).ok_or_else(|| MultiSendError::UnknownDenom { denom: denom.to_string() })
+  }
+
+  // A denom missing from the non-issuer sums means all of its inputs (or outputs) belong to the issuer,
+  // so nothing is transferred between non-issuers and no fee applies.
+  fn non_issuer_amounts(&self, fee_context: &FeeContext) -> (Amount, Amount) {
+    (fee_context.non_issuer_input_sum.amount_of(&self.denom), fee_context.non_issuer_output_sum.amount_of(&self.denom))
+  }
+
+  // burn and commission do not apply to the issuer or the exempt addresses, neither when receiving nor when sending
+  // the denom
+  fn is_fee_exempt(&self, address: &str) -> bool {
+    self.issuer == address || self.exempt_addresses.contains(address)
+  }
+
+  fn burn_rate_of(&self, fee_context: &FeeContext) -> Decimal {
+    self.tier_rate(self.burn_rate, &self.burn_tiers, fee_context)
+  }
+
+  fn commission_rate_of(&self, fee_context: &FeeContext) -> Decimal {
+    self.tier_rate(self.commission_rate, &self.commission_tiers, fee_context)
+  }
+
+  // the rate of the highest tier reached by min(non_issuer_input, non_issuer_output), or `base` below every tier
+  fn tier_rate(&self, base: Decimal, tiers: &[FeeTier], fee_context: &FeeContext) -> Decimal {
+    let (non_issuer_input, non_issuer_output) = self.non_issuer_amounts(fee_context);
+    let transferred = DenomDefinition::min(non_issuer_input, non_issuer_output);
+    tiers.iter().rev().find(|tier| tier.from <= transferred).map_or(base, |tier| tier.rate)
+  }
+
+  // everything `address` pays for sending `amount`, with the fees in the fee denom
+  fn debit(&self, address: &str, amount: Amount, fee_context: &FeeContext) -> Result<FeeDebit, MultiSendError> {
+    let (burn, commission) = self.fee_amounts(address, amount, fee_context)?;
+    let gross = match self.fee_conversion_rate() {
+      Some(_) => amount,
+      None => self.gross_amount(amount, burn, commission)?,
+    };
+    let fee_denom = self.fee_denom_name().to_string();
+    Ok(FeeDebit { address: address.to_string(), denom: self.denom.clone(), principal: amount, fee_denom, burn, commission, gross })
+  }
+
+  fn gross_amount(&self, amount: Amount, burn: Amount, commission: Amount) -> Result<Amount, MultiSendError> {
+    amount.checked_add(burn)
+      .and_then(|sum| sum.checked_add(commission))
+      .ok_or_else(|| MultiSendError::Overflow { denom: self.denom.clone() })
+  }
+
+  // (burn share, commission share) that `address` pays on top of sending `amount`, in the fee denom
+  fn fee_amounts(&self, address: &str, amount: Amount, fee_context: &FeeContext) -> Result<(Amount, Amount), MultiSendError> {
+    if self.has_tx_wide_shares() {
+      return Ok(fee_context.tx_share(&self.denom, address));
+    }
+    let burn = self.limited_share(address, amount, fee_context, (self.min_burn, self.max_burn), DenomDefinition::unlimited_burn)?;
+    let commission = self.limited_share(address, amount, fee_context, (self.min_commission, self.max_commission), DenomDefinition::unlimited_commission)?;
+    Ok((self.convert_fee(burn)?, self.convert_fee(commission)?))
+  }
+
+  // the rate fees are converted at, if they are paid in a denom other than this one
+  fn fee_conversion_rate(&self) -> Option<Decimal> {
+    match &self.fee_denom {
+      Some((fee_denom, rate)) if *fee_denom != self.denom => Some(*rate),
+      _ => None,
+    }
+  }
+
+  // `fee` in the fee denom, rounded up
+  fn convert_fee(&self, fee: Amount) -> Result<Amount, MultiSendError> {
+    match self.fee_conversion_rate() {
+      Some(rate) => rate.checked_mul_rounded(fee, Amount::from(1u8), Amount::from(1u8), Rounding::Ceil)
+        .ok_or_else(|| MultiSendError::Overflow { denom: self.denom.clone() }),
+      None => Ok(fee),
+    }
   }
 
-  fn burn_amount(&self, amount: i128, non_issuer_input: i128, non_issuer_output: i128) -> i128 {
+  // With a separate fee denom, the denom's total fee is converted once, rounding up, and spread over the senders in
+  // proportion to their shares by largest remainder, so that converting does not round every share up again.
+  fn convert_shares(&self, shares: Vec<Amount>) -> Result<Vec<Amount>, MultiSendError> {
+    let overflow = || MultiSendError::Overflow { denom: self.denom.clone() };
+    let total = shares.iter().try_fold(ZERO, |total, share| total.checked_add(*share)).ok_or_else(overflow)?;
+    if self.fee_conversion_rate().is_none() || total == ZERO {
+      return Ok(shares);
+    }
+    amount::split_largest_remainder(self.convert_fee(total)?, &shares).ok_or_else(overflow)
+  }
+
+  fn unlimited_burn(&self, amount: Amount, fee_context: &FeeContext) -> Result<Amount, MultiSendError> {
+    self.fee_share(self.burn_rate_of(fee_context), amount, fee_context, self.rounding.burn_rounding())
+  }
+
+  fn unlimited_commission(&self, amount: Amount, fee_context: &FeeContext) -> Result<Amount, MultiSendError> {
+    if self.rounding != RoundingMode::FloorRemainderToIssuer {
+      return self.fee_share(self.commission_rate_of(fee_context), amount, fee_context, self.rounding.burn_rounding());
+    }
+    // roundup(burn + commission) - rounddown(burn): the exact commission plus the part of the burn that was cut off
+    let overflow = || MultiSendError::Overflow { denom: self.denom.clone() };
+    let total_rate = self.burn_rate_of(fee_context).checked_add(self.commission_rate_of(fee_context)).ok_or_else(overflow)?;
+    let total = self.fee_share(total_rate, amount, fee_context, Rounding::Ceil)?;
+    let burn = self.unlimited_burn(amount, fee_context)?;
+    total.checked_sub(burn).ok_or_else(overflow)
+  }
+
+  // The share computed by `unlimited`, bounded by `limits`. No fee, not even the minimum, applies to the issuer or
+  // when nothing is transferred between non-issuers.
+  fn limited_share(
+    &self,
+    address: &str,
+    amount: Amount,
+    fee_context: &FeeContext,
+    limits: (Option<Amount>, Option<Amount>),
+    unlimited: fn(&DenomDefinition, Amount, &FeeContext) -> Result<Amount, MultiSendError>,
+  ) -> Result<Amount, MultiSendError> {
+    let (non_issuer_input, non_issuer_output) = self.non_issuer_amounts(fee_context);
+    if self.is_fee_exempt(address) || DenomDefinition::min(non_issuer_input, non_issuer_output) == ZERO {
+      return Ok(ZERO);
+    }
+    Ok(DenomDefinition::bound(unlimited(self, amount, fee_context)?, limits))
+  }
+
+  fn bound(fee: Amount, (min, max): (Option<Amount>, Option<Amount>)) -> Amount {
+    let fee = min.map_or(fee, |min| std::cmp::max(fee, min));
+    max.map_or(fee, |max| std::cmp::min(fee, max))
+  }
+
+  // Largest-remainder rounding and tx-wide limits make a sender's share depend on every other sender, so the shares
+  // of such a denom are computed for all of its senders at once, by `fee_context`.
+  fn has_tx_wide_shares(&self) -> bool {
+    self.rounding == RoundingMode::LargestRemainder || self.fee_limit_scope == FeeLimitScope::PerTx
+  }
+
+  // the (burn, commission) share of every non-issuer sender of the denom, in the fee denom
+  fn tx_shares(&self, fee_context: &FeeContext) -> Result<BTreeMap<String, (Amount, Amount)>, MultiSendError> {
+    let senders: Vec<(&String, Amount)> = fee_context.non_issuer_inputs.iter()
+      .map(|(sender, coins)| (sender, coins.amount_of(&self.denom)))
+      .filter(|(_, amount)| *amount != ZERO)
+      .collect();
+    let burns = self.tx_wide_shares(&senders, fee_context, self.burn_rate_of(fee_context), (self.min_burn, self.max_burn), DenomDefinition::unlimited_burn)?;
+    let commissions = self.tx_wide_shares(&senders, fee_context, self.commission_rate_of(fee_context),
+      (self.min_commission, self.max_commission), DenomDefinition::unlimited_commission)?;
+    let (burns, commissions) = (self.convert_shares(burns)?, self.convert_shares(commissions)?);
+    Ok(senders.into_iter().map(|(sender, _)| sender.clone()).zip(burns.into_iter().zip(commissions)).collect())
+  }
+
+  // The shares of `senders` in one fee, in the same order. Largest-remainder rounding rounds the denom's total
+  // roundup(rate * min(non_issuer_input, non_issuer_output)) once and spreads it over the senders in proportion to
+  // their inputs, ties to the smaller address; the other modes round every share on its own. `limits` then bound
+  // every share, or with `FeeLimitScope::PerTx` their total, which is spread over the senders the same way.
+  fn tx_wide_shares(
+    &self,
+    senders: &[(&String, Amount)],
+    fee_context: &FeeContext,
+    rate: Decimal,
+    limits: (Option<Amount>, Option<Amount>),
+    unlimited: fn(&DenomDefinition, Amount, &FeeContext) -> Result<Amount, MultiSendError>,
+  ) -> Result<Vec<Amount>, MultiSendError> {
+    let overflow = || MultiSendError::Overflow { denom: self.denom.clone() };
+    let (non_issuer_input, non_issuer_output) = self.non_issuer_amounts(fee_context);
+    if DenomDefinition::min(non_issuer_input, non_issuer_output) == ZERO {
+      return Ok(vec![ZERO; senders.len()]);
+    }
+    let weights: Vec<Amount> = senders.iter().map(|(_, sent)| *sent).collect();
+    let shares = if self.rounding == RoundingMode::LargestRemainder {
+      let total = self.rounded_share(rate, non_issuer_input, non_issuer_input, non_issuer_output, Rounding::Ceil)?;
+      amount::split_largest_remainder(total, &weights).ok_or_else(overflow)?
+    } else {
+      senders.iter().map(|(_, sent)| unlimited(self, *sent, fee_context)).collect::<Result<Vec<_>, _>>()?
+    };
+    match self.fee_limit_scope {
+      FeeLimitScope::PerAccount => Ok(shares.into_iter().map(|share| DenomDefinition::bound(share, limits)).collect()),
+      FeeLimitScope::PerTx => {
+        let total = shares.iter().try_fold(ZERO, |total, share| total.checked_add(*share)).ok_or_else(overflow)?;
+        let bounded = DenomDefinition::bound(total, limits);
+        if bounded == total {
+          return Ok(shares);
+        }
+        amount::split_largest_remainder(bounded, &weights).ok_or_else(overflow)
+      }
+    }
+  }
+
+  // account_share = round(rate * min(non_issuer_input, non_issuer_output) * amount / non_issuer_input)
+  // The whole expression is evaluated exactly and rounded once; no intermediate product can overflow.
+  fn fee_share(&self, rate: Decimal, amount: Amount, fee_context: &FeeContext, rounding: Rounding) -> Result<Amount, MultiSendError> {
+    let (non_issuer_input, non_issuer_output) = self.non_issuer_amounts(fee_context);
+    self.rounded_share(rate, amount, non_issuer_input, non_issuer_output, rounding)
+  }
+
+  fn rounded_share(&self, rate: Decimal, amount: Amount, non_issuer_input: Amount, non_issuer_output: Amount, rounding: Rounding) -> Result<Amount, MultiSendError> {
     let min = DenomDefinition::min(non_issuer_input, non_issuer_output);
-     (self.burn_rate * ((min * amount / non_issuer_input) as f64)).ceil() as i128
+    if min == ZERO {
+      return Ok(ZERO);
+    }
+    rate.checked_mul_rounded(min, amount, non_issuer_input, rounding)
+      .ok_or_else(|| MultiSendError::Overflow { denom: self.denom.clone() })
   }
 
-  fn commission_amount(&self, amount: i128, non_issuer_input_sum: &HashMap<String, i128>, non_issuer_output_sum: &HashMap<String, i128>) -> i128 {
-    let non_issuer_input = non_issuer_input_sum.get(&self.denom).expect("Coin Not Found");
-    let non_issuer_output = non_issuer_output_sum.get(&self.denom).expect("Coin Not Found");
-    let min = DenomDefinition::min(*non_issuer_input, *non_issuer_output);
-      (self.commission_rate * ((min * amount / non_issuer_input) as f64)).ceil() as i128
+  // the denom the burn and commission are paid in
+  fn fee_denom_name(&self) -> &str {
+    self.fee_denom.as_ref().map_or(&self.denom, |(fee_denom, _)| fee_denom)
   }
-  
-  fn min(x: i128, y: i128) -> i128 {
+
+  // The commission of a tx split over the commission recipients by largest remainder, ties to the recipient listed
+  // first, so every unit is credited to someone.
+  fn commission_split(&self, commission: Amount) -> Result<Vec<(&str, Amount)>, MultiSendError> {
+    if self.commission_recipients.is_empty() {
+      return Ok(vec![(self.issuer.as_str(), commission)]);
+    }
+    let weights: Vec<Amount> = self.commission_recipients.iter().map(|(_, weight)| Amount::from(*weight)).collect();
+    let parts = amount::split_largest_remainder(commission, &weights).ok_or_else(|| MultiSendError::Overflow { denom: self.denom.clone() })?;
+    Ok(self.commission_recipients.iter().map(|(recipient, _)| recipient.as_str()).zip(parts).collect())
+  }
+
+  fn min(x: Amount, y: Amount) -> Amount {
     if x > y { y } else { x }
   }
 }
@@ -206,98 +732,192 @@ fn calculate_balance_changes(
     original_balances: Vec<Balance>,
     definitions: Vec<DenomDefinition>,
     multi_send_tx: MultiSend,
-) -> Result<Vec<Balance>, String> {
-    let token_map: HashMap<String, DenomDefinition> = definitions.into_iter().map(|def| (def.denom.clone(), def)).collect();
-    let account_map: HashMap<String, Vec<Coin>> = original_balances.into_iter().map(|balance| (balance.address.clone(), balance.coins)).collect();
-
-    // check the input amounts and output amounts
-    multi_send_tx.assert_input_output_amounts_should_same()?;
-    multi_send_tx.assert_balances_should_bigger_than_input(&account_map, &token_map)?;
+) -> Result<Vec<BalanceChange>, MultiSendError> {
+    let token_map = DenomDefinition::token_map(definitions)?;
+    let account_map: BTreeMap<String, Coins> = original_balances.into_iter().map(|balance| (balance.address.clone(), balance.coins)).collect();
+    compute_balance_changes(&account_map, &token_map, &multi_send_tx).map(|receipt| receipt.changes)
+}
 
-    let (non_issuer_input_sum, non_issuer_output_sum) = multi_send_tx.get_nonissuer_input_output_amounts(&token_map);
+// The reverse of the balance check: what the senders of `multi_send_tx` pay for its outputs to receive exactly what
+// they list, each input naming the part of the outputs its sender covers. The debits are rounded exactly as
+// `calculate_balance_changes` rounds them, and no balances are needed, so a wallet can show them before signing.
+fn gross_up_debits(definitions: Vec<DenomDefinition>, multi_send_tx: &MultiSend) -> Result<Vec<FeeDebit>, MultiSendError> {
+    let token_map = DenomDefinition::token_map(definitions)?;
+    multi_send_tx.required_debits(&token_map)
+}
 
-    let input_amounts_per_account = multi_send_tx.get_inputs_amounts_per_address();
-    let mut result_balance_map: HashMap<String, HashMap<String, i128>> = HashMap::new();
+// The least balance `sender` needs to deliver exactly `outputs` on its own: per denom, the outputs plus the burn and
+// commission on them, and the fees in a separate fee denom.
+fn gross_up(sender: &str, outputs: Vec<Balance>, definitions: Vec<DenomDefinition>) -> Result<Coins, MultiSendError> {
+    let input = Balance { address: sender.to_string(), coins: MultiSend::sum_amounts(&outputs)? };
+    let multi_send_tx = MultiSend::new(vec![input], outputs)?;
+    let mut gross = Coins::default();
+    for debit in gross_up_debits(definitions, &multi_send_tx)? {
+      for (denom, amount) in debit_spent(&debit)? {
+        add_amount(&mut gross, denom, amount)?;
+      }
+    }
+    Ok(gross)
+}
 
-    // calculate balances by subtracting input amounts
-    for (address, account_token_map) in input_amounts_per_account.iter() {
+// The largest principal of `denom` that `account` can send to `recipient` with what it holds in `original_balances`:
+// principal + burn + commission must not exceed its balance, and fees in a separate fee denom must not exceed its
+// balance of that one. Every candidate goes through the same rules as `calculate_balance_changes`, found by binary
+// search. The gross debit only grows with the principal within a tier, so each tier is searched on its own, from
+// the highest one down.
+fn max_sendable(
+    original_balances: &[Balance],
+    definitions: &[DenomDefinition],
+    account: &str,
+    denom: &str,
+    recipient: &str,
+) -> Result<Amount, MultiSendError> {
+    let token_map = DenomDefinition::token_map(definitions.iter().cloned())?;
+    let account_map: BTreeMap<String, Coins> = original_balances.iter().map(|balance| (balance.address.clone(), balance.coins.clone())).collect();
+    let token_denom = DenomDefinition::lookup(&token_map, denom)?;
+    let balance = account_map.get(account).ok_or_else(|| MultiSendError::UnknownAccount { address: account.to_string() })?.amount_of(denom);
+    if balance == ZERO {
+      return Ok(ZERO);
+    }
 
-      if let None = result_balance_map.get(address) {
-        result_balance_map.insert(address.clone(), HashMap::new());
+    let can_send = |principal: Amount| -> Result<bool, MultiSendError> {
+      let mut coins = Coins::default();
+      add_amount(&mut coins, denom, principal)?;
+      let multi_send_tx = MultiSend::new(
+        vec![Balance { address: account.to_string(), coins: coins.clone() }],
+        vec![Balance { address: recipient.to_string(), coins }],
+      )?;
+      match compute_balance_changes(&account_map, &token_map, &multi_send_tx) {
+        Ok(_) => Ok(true),
+        // a gross debit beyond the amount range exceeds any balance
+        Err(MultiSendError::InsufficientBalance { .. } | MultiSendError::Overflow { .. }) => Ok(false),
+        Err(err) => Err(err),
       }
+    };
 
-      for (denom, value) in account_token_map.iter() {
-        let token_denom: &DenomDefinition = token_map.get(denom).expect("Invalid Token Found");
-        let caculated_value = token_denom.calculated_amount(*value, &non_issuer_input_sum, &non_issuer_output_sum);
-        
-        let result_account_token_map = result_balance_map.get_mut(address).unwrap();
-        if let Some(token_value) = result_account_token_map.get_mut(denom) {
-          *token_value = *token_value - caculated_value;
-        } else {
-          result_account_token_map.insert(denom.clone(), -caculated_value);
-        }
+    let overflow = || MultiSendError::Overflow { denom: denom.to_string() };
+    let one = Amount::from(1u8);
+    let mut tier_starts: Vec<Amount> = token_denom.burn_tiers.iter().chain(token_denom.commission_tiers.iter())
+      .map(|tier| tier.from)
+      .filter(|from| *from > one && *from <= balance)
+      .collect();
+    tier_starts.push(one);
+    tier_starts.sort();
+    tier_starts.dedup();
 
-        // add commission_rate to issuer
-        let commission_value = token_denom.commission_amount(*value, &non_issuer_input_sum, &non_issuer_output_sum);
-        
-        if let Some(issuer_account_token_map) = result_balance_map.get_mut(&token_denom.issuer) {
-          if let Some(token_value) = issuer_account_token_map.get_mut(denom) {
-            *token_value = *token_value + commission_value;
-          } else {
-            issuer_account_token_map.insert(denom.clone(), commission_value);
-          }
-        } else {
-          let mut new_coins = HashMap::new();
-          new_coins.insert(denom.clone(), commission_value);
-          result_balance_map.insert(token_denom.issuer.clone(), new_coins);
+    let mut high = balance;
+    for low in tier_starts.into_iter().rev() {
+      if can_send(low)? {
+        // can_send(low) holds; find the largest principal in [low, high] that can still be sent
+        let mut low = low;
+        while low < high {
+          let gap = high.checked_sub(low).and_then(|gap| gap.checked_add(one)).ok_or_else(overflow)?;
+          let (half, _) = amount::mul_div_rem(gap, one, Amount::from(2u8)).ok_or_else(overflow)?;
+          let middle = low.checked_add(half).ok_or_else(overflow)?;
+          if can_send(middle)? { low = middle; } else { high = middle.checked_sub(one).unwrap_or(ZERO); }
         }
-        
+        return Ok(low);
       }
+      high = low.checked_sub(one).unwrap_or(ZERO);
     }
+    Ok(ZERO)
+}
 
-    // calculate balances by adding output amounts
-    let output_amounts_per_account = multi_send_tx.get_outputs_amounts_per_address();
-    for (address, account_token_map) in output_amounts_per_account.iter() {
+// The core of `calculate_balance_changes`, working on borrowed state so `Bank` can share it.
+fn compute_balance_changes(
+    account_map: &BTreeMap<String, Coins>,
+    token_map: &BTreeMap<String, DenomDefinition>,
+    multi_send_tx: &MultiSend,
+) -> Result<Receipt, MultiSendError> {
+    // check the input amounts and output amounts
+    multi_send_tx.validate_basic()?;
+    multi_send_tx.assert_input_output_amounts_should_same()?;
+    let fee_context = multi_send_tx.fee_context(token_map)?;
+    multi_send_tx.assert_balances_should_bigger_than_input(account_map, token_map, &fee_context)?;
+
+    let mut result_balance_map: BTreeMap<String, SignedCoins> = BTreeMap::new();
+    let mut burned = Coins::default();
+    let mut commission = Coins::default();
+    // commission per transferred denom, in its fee denom, before it is split between the recipients
+    let mut commission_per_denom = Coins::default();
+    let debits = multi_send_tx.fee_debits(token_map, &fee_context)?;
+    let mut commission_credits: BTreeMap<(String, String, String), Amount> = BTreeMap::new();
 
-      if let None = result_balance_map.get(address) {
-        result_balance_map.insert(address.clone(), HashMap::new());
+    // calculate balances by subtracting input amounts
+    for debit in &debits {
+      let result_coins = result_balance_map.entry(debit.address.clone()).or_default();
+      for (denom, amount) in debit_spent(debit)? {
+        add_change(result_coins, denom, SignedAmount::negative(amount))?;
       }
+      add_amount(&mut burned, &debit.fee_denom, debit.burn)?;
+      add_amount(&mut commission_per_denom, &debit.denom, debit.commission)?;
+    }
 
-      for (denom, value) in account_token_map.iter() {
-        let _result_account_token_map = result_balance_map.get_mut(address).unwrap();
-        if let Some(token_value) = _result_account_token_map.get_mut(denom) {
-          *token_value = *token_value + value;
-        } else {
-          _result_account_token_map.insert(denom.clone(), *value);
-        }
+    // credit the commission of every denom to its recipients, split once for the whole tx
+    for coin in &commission_per_denom {
+      let token_denom = DenomDefinition::lookup(token_map, &coin.denom)?;
+      let fee_denom = token_denom.fee_denom_name();
+      add_amount(&mut commission, fee_denom, coin.amount)?;
+      for (recipient, amount) in token_denom.commission_split(coin.amount)? {
+        if amount == ZERO { continue; }
+        add_change(result_balance_map.entry(recipient.to_string()).or_default(), fee_denom, SignedAmount::positive(amount))?;
+        let credit = commission_credits.entry((recipient.to_string(), coin.denom.clone(), fee_denom.to_string())).or_insert(ZERO);
+        *credit = credit.checked_add(amount).ok_or_else(|| MultiSendError::Overflow { denom: fee_denom.to_string() })?;
       }
     }
 
-    // convert result_balance_map to Balance vector
-    let result_balances: Vec<Balance> = result_balance_map.iter().map(|(address, account_token_map)| 
-      Balance {
-        address: address.clone(),
-        coins: account_token_map.iter().map(|(denom, amount)| Coin { denom: denom.clone(), amount: *amount })
-          .collect::<Vec<Coin>>()
-          .into_iter()
-          .filter(| coin | coin.amount != 0)
-          .collect()
+    // calculate balances by adding output amounts
+    let output_amounts_per_account = multi_send_tx.get_outputs_amounts_per_address()?;
+    for (address, account_coins) in output_amounts_per_account.iter() {
+      let result_coins = result_balance_map.entry(address.clone()).or_default();
+      for coin in account_coins {
+        add_change(result_coins, &coin.denom, SignedAmount::positive(coin.amount))?;
       }
-    ).collect();
-    
-    Ok(result_balances.into_iter().filter(|balance| balance.coins.len() > 0).collect())
+    }
+
+    assert_supply_only_changes_by_burn(&result_balance_map, &burned)?;
+
+    // convert result_balance_map to Balance vector; `Coins` already dropped every denom whose change is zero.
+    // Both levels are ordered, so the output is sorted by address and then by denom on every run.
+    let changes = result_balance_map.into_iter()
+      .filter(|(_, coins)| !coins.is_empty())
+      .map(|(address, coins)| BalanceChange { address, coins })
+      .collect();
+    let commission_credits = commission_credits.into_iter()
+      .map(|((recipient, denom, fee_denom), amount)| CommissionCredit { recipient, denom, fee_denom, amount })
+      .collect();
+    Ok(Receipt { changes, debits, commission_credits, burned, commission })
+}
+
+// Every coin taken from the inputs must reach an output or a commission recipient, or be burnt, so for each denom
+// the balance changes have to add up to exactly minus the burned amount.
+fn assert_supply_only_changes_by_burn(result_balance_map: &BTreeMap<String, SignedCoins>, burned: &Coins) -> Result<(), MultiSendError> {
+    let mut delta_sum = SignedCoins::default();
+    for coin in result_balance_map.values().flat_map(|coins| coins.iter()) {
+      add_change(&mut delta_sum, &coin.denom, coin.amount)?;
+    }
+    let denoms: BTreeSet<&String> = delta_sum.iter().map(|coin| &coin.denom).chain(burned.iter().map(|coin| &coin.denom)).collect();
+    for denom in denoms {
+      let (delta, burn) = (delta_sum.amount_of(denom), burned.amount_of(denom));
+      if delta.checked_add(SignedAmount::positive(burn)) != Some(SignedAmount::default()) {
+        return Err(MultiSendError::SupplyMismatch { denom: denom.clone(), delta, burned: burn });
+      }
+    }
+    Ok(())
 }
 
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::collections::HashMap;
+  use crate::testing::{amount, amounts, balance, change};
 
-  fn check_results(results: &Vec<Balance>, expected_results: &Vec<Balance>) -> bool {
+  fn check_results(results: &[BalanceChange], expected_results: &[BalanceChange]) -> bool {
     for balance in results.iter() {
       if let Some(expected_balance) = expected_results.iter().find(|&bal| bal.address == balance.address && bal.coins.len() == balance.coins.len()) {
         for coin in &balance.coins {
-          if let None = expected_balance.coins.iter().find(|&c| c.amount == coin.amount) {
+          if !expected_balance.coins.iter().any(|c| c.amount == coin.amount) {
             return false;
           }
         }
@@ -308,87 +928,39 @@ mod tests {
     results.len() == expected_results.len()
   }
 
+  // (burn, commission) pairs as amounts
+  fn pairs(values: &[(u128, u128)]) -> Vec<(Amount, Amount)> {
+    values.iter().map(|(burn, commission)| (amount(*burn), amount(*commission))).collect()
+  }
+
   #[test]
+  #[allow(clippy::bool_comparison)]
   fn check_test_case_1() {
     let definitions: Vec<DenomDefinition> = vec![
-      DenomDefinition {
-          denom: "denom1".to_string(),
-          issuer: "issuer_account_A".to_string(),
-          burn_rate: 0.08,
-          commission_rate: 0.12,
-      },
-      DenomDefinition {
-          denom: "denom2".to_string(),
-          issuer: "issuer_account_B".to_string(),
-          burn_rate: 1.0,
-          commission_rate: 0.0,
-      }
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12)),
+      DenomDefinition::new("denom2", "issuer_account_B", Decimal::one(), Decimal::zero())
     ];
 
     let orig_balances: Vec<Balance> = vec![
-      Balance {
-        address: "account1".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000_000} ]
-      },
-      
-      Balance {
-        address: "account2".to_string(),
-        coins: vec![ Coin { denom: "denom2".to_string(), amount: 1000_000} ]
-      }
+      balance("account1", "1000000denom1"),
+      balance("account2", "1000000denom2")
     ];
 
     let multi_send = MultiSend {
       inputs: vec![
-        Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 1000}
-          ]
-        },
-        Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom2".to_string(), amount: 1000}
-          ]
-        }
+        balance("account1", "1000denom1"),
+        balance("account2", "1000denom2")
       ],
       outputs: vec![
-        Balance{
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 1000},
-            Coin { denom: "denom2".to_string(), amount: 1000}
-          ]
-        }
+        balance("account_recipient", "1000denom1,1000denom2")
       ]
     };
 
     let expected_result = vec![
-      Balance {
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 1000},
-            Coin { denom: "denom2".to_string(), amount: 1000}
-          ]
-      },
-      Balance {
-          address: "issuer_account_A".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 120},
-          ]
-      },
-      Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: -1200},
-          ]
-      },
-      Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom2".to_string(), amount: -2000},
-          ]
-      }
+      change("account_recipient", "1000denom1,1000denom2"),
+      change("issuer_account_A", "120denom1"),
+      change("account1", "-1200denom1"),
+      change("account2", "-2000denom2")
     ];
     
     let result = calculate_balance_changes(
@@ -403,84 +975,33 @@ mod tests {
 
   
   #[test]
+  #[allow(clippy::bool_comparison)]
   fn check_test_case_2() {
     let definitions: Vec<DenomDefinition> = vec![
-      DenomDefinition {
-          denom: "denom1".to_string(),
-          issuer: "issuer_account_A".to_string(),
-          burn_rate: 0.08,
-          commission_rate: 0.12,
-      }
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12))
     ];
 
     let orig_balances: Vec<Balance> = vec![
-      Balance {
-        address: "account1".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000_000} ]
-      },
-      
-      Balance {
-        address: "account2".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000_000} ]
-      }
+      balance("account1", "1000000denom1"),
+      balance("account2", "1000000denom1")
     ];
 
     let multi_send = MultiSend {
       inputs: vec![
-        Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 650}
-          ]
-        },
-        Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 350}
-          ]
-        }
+        balance("account1", "650denom1"),
+        balance("account2", "350denom1")
       ],
       outputs: vec![
-        Balance {
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 500},
-          ]
-        },
-        Balance {
-          address: "issuer_account_A".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 500},
-          ]
-        }
+        balance("account_recipient", "500denom1"),
+        balance("issuer_account_A", "500denom1")
       ]
     };
 
     let expected_result = vec![
-      Balance {
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 500},
-          ]
-      },
-      Balance {
-          address: "issuer_account_A".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 560},
-          ]
-      },
-      Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: -715},
-          ]
-      },
-      Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: -385},
-          ]
-      }
+      change("account_recipient", "500denom1"),
+      change("issuer_account_A", "560denom1"),
+      change("account1", "-715denom1"),
+      change("account2", "-385denom1")
     ];
     
     let result = calculate_balance_changes(
@@ -496,37 +1017,19 @@ mod tests {
   #[test]
   fn check_test_case_3() {
     let definitions: Vec<DenomDefinition> = vec![
-      DenomDefinition {
-          denom: "denom1".to_string(),
-          issuer: "issuer_account_A".to_string(),
-          burn_rate: 0.0,
-          commission_rate: 0.0,
-      }
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero())
     ];
 
     let orig_balances: Vec<Balance> = vec![
-      Balance {
-        address: "account1".to_string(),
-        coins: vec![]
-      }
+      balance("account1", "")
     ];
 
     let multi_send = MultiSend {
       inputs: vec![
-        Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 350}
-          ]
-        }
+        balance("account1", "350denom1")
       ],
       outputs: vec![
-        Balance{
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 350}
-          ]
-        }
+        balance("account_recipient", "350denom1")
       ]
     };
     
@@ -536,43 +1039,30 @@ mod tests {
       multi_send
     );
 
-    assert!(result.unwrap_err() == "Insufficient balance for token: denom1 in address: account1", "Test3 failed");
+    assert_eq!(result.unwrap_err(), MultiSendError::InsufficientBalance {
+      address: "account1".to_string(),
+      denom: "denom1".to_string(),
+      required: amount(350),
+      available: amount(0),
+    }, "Test3 failed");
   }
 
   #[test]
   fn check_test_case_4() {
     let definitions: Vec<DenomDefinition> = vec![
-      DenomDefinition {
-          denom: "denom1".to_string(),
-          issuer: "issuer_account_A".to_string(),
-          burn_rate: 0.0,
-          commission_rate: 0.0,
-      }
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero())
     ];
 
     let orig_balances: Vec<Balance> = vec![
-      Balance {
-        address: "account1".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000_000} ]
-      }
+      balance("account1", "1000000denom1")
     ];
 
     let multi_send = MultiSend {
       inputs: vec![
-        Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 350}
-          ]
-        }
+        balance("account1", "350denom1")
       ],
       outputs: vec![
-        Balance{
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 450}
-          ]
-        }
+        balance("account_recipient", "450denom1")
       ]
     };
     
@@ -582,82 +1072,38 @@ mod tests {
       multi_send
     );
 
-    assert!(result.unwrap_err() == "Input and Output token amount mismatches for token: denom1", "Test4 failed");
+    assert_eq!(result.unwrap_err(), MultiSendError::AmountMismatch(vec![
+      DenomMismatch { denom: "denom1".to_string(), inputs: amount(350), outputs: amount(450) }
+    ]), "Test4 failed");
   }
 
   #[test]
+  #[allow(clippy::bool_comparison)]
   fn check_test_case_5() {
     let definitions: Vec<DenomDefinition> = vec![
-      DenomDefinition {
-          denom: "denom1".to_string(),
-          issuer: "issuer_account_A".to_string(),
-          burn_rate: 0.01,
-          commission_rate: 0.01,
-      }
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(1), Decimal::percent(1))
     ];
 
     let orig_balances: Vec<Balance> = vec![
-      Balance {
-        address: "account1".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000} ]
-      },
-      
-      Balance {
-        address: "account2".to_string(),
-        coins: vec![ Coin { denom: "denom1".to_string(), amount: 1000} ]
-      }
+      balance("account1", "1000denom1"),
+      balance("account2", "1000denom1")
     ];
 
     let multi_send = MultiSend {
       inputs: vec![
-        Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 1}
-          ]
-        },
-        Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 1}
-          ]
-        }
+        balance("account1", "1denom1"),
+        balance("account2", "1denom1")
       ],
       outputs: vec![
-        Balance{
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 2},
-          ]
-        }
+        balance("account_recipient", "2denom1")
       ]
     };
 
     let expected_result = vec![
-      Balance {
-          address: "account_recipient".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 2},
-          ]
-      },
-      Balance {
-          address: "issuer_account_A".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: 2},
-          ]
-      },
-      Balance {
-          address: "account1".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: -3},
-          ]
-      },
-      Balance {
-          address: "account2".to_string(),
-          coins: vec![
-            Coin { denom: "denom1".to_string(), amount: -3},
-          ]
-      }
+      change("account_recipient", "2denom1"),
+      change("issuer_account_A", "2denom1"),
+      change("account1", "-3denom1"),
+      change("account2", "-3denom1")
     ];
     
     let result = calculate_balance_changes(
@@ -669,4 +1115,983 @@ mod tests {
     assert!(result.is_ok() && check_results(&result.unwrap(), &expected_result) == true, "Result Mismatch");
 
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn check_fee_is_exact_above_f64_precision() {
+    let definition = DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(3));
+
+    // 2^60 + 7 is not representable as f64; 10% of it is 115292150460684698.3 and 3% is 34587645138205409.49
+    let sent = amount((1 << 60) + 7);
+    let non_issuer_sum = Coins::new(vec![Coin { denom: "denom1".to_string(), amount: sent }]).unwrap();
+    let fee_context = FeeContext { non_issuer_input_sum: non_issuer_sum.clone(), non_issuer_output_sum: non_issuer_sum, ..FeeContext::default() };
+    assert_eq!(definition.fee_amounts("account1", sent, &fee_context), Ok((amount(115_292_150_460_684_699), amount(34_587_645_138_205_410))));
+  }
+
+  #[test]
+  fn check_unknown_account_is_rejected() {
+    let definitions: Vec<DenomDefinition> = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero())
+    ];
+
+    let multi_send = MultiSend {
+      inputs: vec![
+        balance("account1", "350denom1")
+      ],
+      outputs: vec![
+        balance("account_recipient", "350denom1")
+      ]
+    };
+
+    let err = calculate_balance_changes(vec![], definitions, multi_send).unwrap_err();
+    assert_eq!(err, MultiSendError::UnknownAccount { address: "account1".to_string() });
+    assert_eq!((err.codespace(), err.code()), ("multisend", 5));
+  }
+
+  fn denom1_definition() -> Vec<DenomDefinition> {
+    vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12))
+    ]
+  }
+
+  #[test]
+  fn check_amount_overflow_is_rejected() {
+    let max = Amount::MAX.to_string();
+    let overflow = MultiSendError::Overflow { denom: "denom1".to_string() };
+
+    // the input total of two maximal inputs does not fit
+    let orig_balances = vec![balance("account1", &format!("{}denom1", max)), balance("account2", &format!("{}denom1", max))];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", &format!("{}denom1", max)), balance("account2", &format!("{}denom1", max))],
+      vec![balance("account_recipient", &format!("{}denom1", max)), balance("account_recipient", &format!("{}denom1", max))],
+    ).unwrap();
+    assert_eq!(calculate_balance_changes(orig_balances, denom1_definition(), multi_send).unwrap_err(), overflow);
+
+    // sending the maximal amount is fine, but adding burn and commission on top of it is not
+    let orig_balances = vec![balance("account1", &format!("{}denom1", max))];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", &format!("{}denom1", max))],
+      vec![balance("account_recipient", &format!("{}denom1", max))],
+    ).unwrap();
+    assert_eq!(calculate_balance_changes(orig_balances, denom1_definition(), multi_send).unwrap_err(), overflow);
+  }
+
+  #[test]
+  fn check_large_18_decimal_amounts_are_exact() {
+    // a trillion tokens with 18 decimals; rate * amount in fixed-point alone would not fit in i128
+    let amount = 10u128.pow(30);
+    let orig_balances = vec![balance("account1", &format!("{}denom1", amount * 2))];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", &format!("{}denom1", amount + 1))],
+      vec![balance("account_recipient", &format!("{}denom1", amount + 1))],
+    ).unwrap();
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send).unwrap();
+    let burn = amount * 8 / 100 + 1;
+    let commission = amount * 12 / 100 + 1;
+    let result: Vec<String> = result.iter().map(|change| change.to_string()).collect();
+    assert_eq!(result, vec![
+      format!("account1:-{}denom1", amount + 1 + burn + commission),
+      format!("account_recipient:{}denom1", amount + 1),
+      format!("issuer_account_A:{}denom1", commission),
+    ]);
+  }
+
+  #[test]
+  fn check_undefined_denom_is_rejected() {
+    let orig_balances: Vec<Balance> = vec![
+      balance("account1", "1000denom9")
+    ];
+
+    let multi_send = MultiSend {
+      inputs: vec![
+        balance("account1", "500denom9")
+      ],
+      outputs: vec![
+        balance("account_recipient", "500denom9")
+      ]
+    };
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(result.unwrap_err(), MultiSendError::UnknownDenom { denom: "denom9".to_string() });
+  }
+
+  #[test]
+  fn check_issuer_only_inputs_pay_no_fee() {
+    let orig_balances: Vec<Balance> = vec![
+      balance("issuer_account_A", "1000denom1")
+    ];
+
+    let multi_send = MultiSend {
+      inputs: vec![
+        balance("issuer_account_A", "1000denom1")
+      ],
+      outputs: vec![
+        balance("account_recipient", "1000denom1")
+      ]
+    };
+
+    let expected_result = vec![
+      change("account_recipient", "1000denom1"),
+      change("issuer_account_A", "-1000denom1"),
+    ];
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(result.unwrap(), expected_result);
+  }
+
+  #[test]
+  fn check_issuer_only_outputs_pay_no_fee() {
+    let orig_balances: Vec<Balance> = vec![
+      balance("account1", "1000denom1")
+    ];
+
+    let multi_send = MultiSend {
+      inputs: vec![
+        balance("account1", "1000denom1")
+      ],
+      outputs: vec![
+        balance("issuer_account_A", "1000denom1")
+      ]
+    };
+
+    let expected_result = vec![
+      change("account1", "-1000denom1"),
+      change("issuer_account_A", "1000denom1"),
+    ];
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(result.unwrap(), expected_result);
+  }
+
+  #[test]
+  fn check_output_only_denom_is_rejected() {
+    let orig_balances: Vec<Balance> = vec![
+      balance("account1", "1000denom1")
+    ];
+
+    let multi_send = MultiSend {
+      inputs: vec![
+        balance("account1", "350denom1")
+      ],
+      outputs: vec![
+        balance("account_recipient", "300denom1,500denom9")
+      ]
+    };
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(result.unwrap_err(), MultiSendError::AmountMismatch(vec![
+      DenomMismatch { denom: "denom1".to_string(), inputs: amount(350), outputs: amount(300) },
+      DenomMismatch { denom: "denom9".to_string(), inputs: amount(0), outputs: amount(500) },
+    ]));
+  }
+
+  // xorshift64*, so the property test below is reproducible without extra dependencies
+  struct Rng(u64);
+
+  impl Rng {
+    fn next(&mut self) -> u64 {
+      self.0 ^= self.0 >> 12;
+      self.0 ^= self.0 << 25;
+      self.0 ^= self.0 >> 27;
+      self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 { self.next() % n }
+  }
+
+  fn random_definitions(rng: &mut Rng) -> Vec<DenomDefinition> {
+    let modes = [RoundingMode::Ceil, RoundingMode::Floor, RoundingMode::HalfEven, RoundingMode::FloorRemainderToIssuer,
+      RoundingMode::LargestRemainder];
+    ["denom1", "denom2"].iter().zip(["issuer_account_A", "issuer_account_B"]).map(|(denom, issuer)| {
+      let burn_rate = Decimal::from_ratio(rng.below(300) as i128, 1000).unwrap();
+      let commission_rate = Decimal::from_ratio(rng.below(300) as i128, 1000).unwrap();
+      let mut definition = DenomDefinition::new(denom, issuer, burn_rate, commission_rate).with_rounding(modes[rng.below(modes.len() as u64) as usize]);
+      if rng.below(3) == 0 {
+        definition = definition.with_min_burn(amount(rng.below(5).into())).with_max_commission(amount(rng.below(50).into()));
+      }
+      if rng.below(2) == 0 {
+        definition = definition.with_fee_limit_scope(FeeLimitScope::PerTx);
+      }
+      if rng.below(3) == 0 {
+        let tier = FeeTier { from: amount(rng.below(200).into()), rate: Decimal::from_ratio(rng.below(300) as i128, 1000).unwrap() };
+        definition = definition.with_burn_tiers(vec![tier]).with_commission_tiers(vec![tier]);
+      }
+      definition
+    }).collect()
+  }
+
+  fn random_multi_send(rng: &mut Rng, balanced: bool) -> MultiSend {
+    let accounts = ["account1", "account2", "account3", "issuer_account_A", "issuer_account_B"];
+    let denoms = ["denom1", "denom2", "denom3"];
+    let input_count = 1 + rng.below(3) as usize;
+    let output_count = 1 + rng.below(3) as usize;
+
+    let mut inputs = vec![];
+    let mut outputs: Vec<Balance> = (0..output_count).map(|_| Balance {
+      address: accounts[rng.below(accounts.len() as u64) as usize].to_string(),
+      coins: Coins::default(),
+    }).collect();
+    for _ in 0..input_count {
+      let mut coins = Coins::default();
+      for (i, denom) in denoms[..2].iter().enumerate() {
+        if rng.below(2) == 0 && (i == 0 || !coins.is_empty()) { continue; }
+        let value = 1 + rng.below(1000);
+        coins.add_amount(denom, amount(value.into())).unwrap();
+        // spread the same amount over the outputs so the tx stays balanced
+        let mut remaining = value;
+        for (i, output) in outputs.iter_mut().enumerate() {
+          let part = if i + 1 == output_count { remaining } else { rng.below(remaining + 1) };
+          remaining -= part;
+          output.coins.add_amount(denom, amount(part.into())).unwrap();
+        }
+      }
+      inputs.push(Balance { address: accounts[rng.below(accounts.len() as u64) as usize].to_string(), coins });
+    }
+    if !balanced {
+      let denom = denoms[rng.below(denoms.len() as u64) as usize];
+      outputs[0].coins.add_amount(denom, amount((1 + rng.below(100)).into())).unwrap();
+    }
+    outputs.retain(|output| !output.coins.is_empty());
+    MultiSend::new(inputs, outputs).unwrap()
+  }
+
+  fn burned_per_denom(multi_send: &MultiSend, definitions: &[DenomDefinition]) -> HashMap<String, Amount> {
+    let token_map: BTreeMap<String, DenomDefinition> = definitions.iter().map(|def| (def.denom.clone(), def.clone())).collect();
+    let fee_context = multi_send.fee_context(&token_map).unwrap();
+    let mut burned = HashMap::new();
+    for (address, account_coins) in multi_send.get_inputs_amounts_per_address().unwrap() {
+      for coin in &account_coins {
+        let token_denom = &token_map[&coin.denom];
+        let total = burned.entry(coin.denom.clone()).or_insert(ZERO);
+        *total = total.checked_add(token_denom.fee_amounts(&address, coin.amount, &fee_context).unwrap().0).unwrap();
+      }
+    }
+    burned
+  }
+
+  #[test]
+  fn property_accepted_tx_only_changes_supply_by_burns() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let mut accepted = 0;
+    for _ in 0..2000 {
+      let definitions = random_definitions(&mut rng);
+      let balanced = rng.below(4) != 0;
+      let multi_send = random_multi_send(&mut rng, balanced);
+      let orig_balances: Vec<Balance> = ["account1", "account2", "account3", "issuer_account_A", "issuer_account_B"].iter().map(|address| Balance {
+        address: address.to_string(),
+        coins: Coins::new(vec![
+          Coin { denom: "denom1".to_string(), amount: amount((1 + rng.below(6000)).into()) },
+          Coin { denom: "denom2".to_string(), amount: amount((1 + rng.below(6000)).into()) },
+        ]).unwrap(),
+      }).collect();
+      let burned = burned_per_denom(&multi_send, &definitions);
+
+      match calculate_balance_changes(orig_balances, definitions, multi_send) {
+        Ok(changes) => {
+          assert!(balanced, "unbalanced tx was accepted");
+          accepted += 1;
+          let mut supply_change = SignedCoins::default();
+          for coin in changes.iter().flat_map(|change| change.coins.iter()) {
+            supply_change.add_amount(&coin.denom, coin.amount).unwrap();
+          }
+          for denom in ["denom1", "denom2"] {
+            let burned = SignedAmount::negative(burned.get(denom).copied().unwrap_or(ZERO));
+            assert_eq!(supply_change.amount_of(denom), burned, "supply of {} changed by more than the burn", denom);
+          }
+        }
+        Err(err) => assert!(balanced || matches!(err, MultiSendError::AmountMismatch(_)), "unexpected error {:?}", err),
+      }
+    }
+    assert!(accepted > 100, "too few accepted txs to be meaningful: {}", accepted);
+  }
+
+  #[test]
+  fn check_validate_basic_rejections() {
+    let cases = vec![
+      (vec![], vec![balance("account_recipient", "500denom1")], MultiSendError::NoInputs),
+      (vec![balance("account1", "500denom1")], vec![], MultiSendError::NoOutputs),
+      (vec![balance("", "500denom1")], vec![balance("account_recipient", "500denom1")], MultiSendError::EmptyAddress),
+      (vec![balance("account1", "")], vec![balance("account_recipient", "500denom1")],
+        MultiSendError::EmptyCoins { address: "account1".to_string() }),
+    ];
+
+    for (inputs, outputs, expected) in cases {
+      assert_eq!(MultiSend::new(inputs, outputs).unwrap_err(), expected);
+    }
+
+    let bad_denom = Balance::new("account1", vec![Coin { denom: "d".to_string(), amount: amount(500) }]).unwrap();
+    assert_eq!(MultiSend::new(vec![bad_denom], vec![balance("account_recipient", "500denom1")]).unwrap_err(),
+      MultiSendError::InvalidDenom { denom: "d".to_string() });
+
+    // zero amounts and duplicate denoms cannot even form a `Balance`
+    let zero = vec![Coin { denom: "denom1".to_string(), amount: amount(500) }, Coin { denom: "denom2".to_string(), amount: amount(0) }];
+    assert_eq!(Balance::new("account_recipient", zero).unwrap_err(),
+      MultiSendError::InvalidAmount { address: "account_recipient".to_string(), denom: "denom2".to_string(), amount: amount(0) });
+    let duplicate = vec![Coin { denom: "denom1".to_string(), amount: amount(250) }, Coin { denom: "denom1".to_string(), amount: amount(250) }];
+    assert_eq!(Balance::new("account1", duplicate).unwrap_err(),
+      MultiSendError::DuplicateDenom { address: "account1".to_string(), denom: "denom1".to_string() });
+  }
+
+  #[test]
+  fn check_negative_input_cannot_pull_funds() {
+    // a negative input would credit the sender and debit the recipient; amounts are unsigned, so it cannot be built
+    assert_eq!("-500denom1".parse::<Coins>(), Err(coins::ParseCoinError::NegativeAmount("-500denom1".to_string())));
+    // a balance change, on the other hand, must be able to go negative
+    assert_eq!(change("account1", "-500denom1").to_string(), "account1:-500denom1");
+  }
+
+  #[test]
+  fn check_readme_example_issuer_is_exempt() {
+    // burn_rate 10%, inputs 60 and 90 plus 25 from the issuer, outputs 50 and 25 plus 100 to the issuer:
+    // total burn is 10% of min(150, 75) = 7.5, shared as roundup(3) = 3 and roundup(4.5) = 5
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::zero())
+    ];
+
+    // the issuer holds exactly what it sends, so charging it any fee would be rejected
+    let orig_balances = vec![
+      balance("account1", "63denom1"),
+      balance("account2", "95denom1"),
+      balance("issuer_account_A", "25denom1"),
+    ];
+
+    let multi_send = MultiSend::new(
+      vec![
+        balance("account1", "60denom1"),
+        balance("account2", "90denom1"),
+        balance("issuer_account_A", "25denom1"),
+      ],
+      vec![
+        balance("account_recipient1", "50denom1"),
+        balance("issuer_account_A", "100denom1"),
+        balance("account_recipient2", "25denom1"),
+      ],
+    ).unwrap();
+
+    let expected_result = vec![
+      change("account1", "-63denom1"),
+      change("account2", "-95denom1"),
+      change("account_recipient1", "50denom1"),
+      change("account_recipient2", "25denom1"),
+      change("issuer_account_A", "75denom1"),
+    ];
+
+    let result = calculate_balance_changes(orig_balances, definitions, multi_send);
+    assert_eq!(result.unwrap(), expected_result);
+  }
+
+  #[test]
+  fn check_readme_example_under_each_rounding_mode() {
+    // exact shares of the 10% burn are 3 (account1) and 4.5 (account2), 7.5 in total
+    let cases = [
+      (RoundingMode::Ceil, "account1:-63denom1;account2:-95denom1", "8denom1", ""),
+      (RoundingMode::Floor, "account1:-63denom1;account2:-94denom1", "7denom1", ""),
+      (RoundingMode::HalfEven, "account1:-63denom1;account2:-94denom1", "7denom1", ""),
+      // account2 pays the rounded up 5, of which 4 is burnt and 1 goes to the issuer
+      (RoundingMode::FloorRemainderToIssuer, "account1:-63denom1;account2:-95denom1", "7denom1", "1denom1"),
+      // the total of 8 is split 3.2 / 4.8; the left over unit goes to the larger remainder
+      (RoundingMode::LargestRemainder, "account1:-63denom1;account2:-95denom1", "8denom1", ""),
+    ];
+
+    for (rounding, debits, burned, commission) in cases {
+      let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::zero()).with_rounding(rounding)];
+      let orig_balances = vec![balance("account1", "63denom1"), balance("account2", "95denom1"), balance("issuer_account_A", "25denom1")];
+      let multi_send = MultiSend::new(
+        vec![balance("account1", "60denom1"), balance("account2", "90denom1"), balance("issuer_account_A", "25denom1")],
+        vec![balance("account_recipient1", "50denom1"), balance("issuer_account_A", "100denom1"), balance("account_recipient2", "25denom1")],
+      ).unwrap();
+
+      let receipt = receipt_for(orig_balances, definitions, multi_send);
+      let changes: Vec<String> = receipt.changes.iter().map(|change| change.to_string()).collect();
+      assert_eq!(changes[..2].join(";"), debits, "{:?}", rounding);
+      assert_eq!(receipt.burned.to_string(), burned, "{:?}", rounding);
+      assert_eq!(receipt.commission.to_string(), commission, "{:?}", rounding);
+    }
+  }
+
+  #[test]
+  fn check_floor_remainder_to_issuer_rounds_the_total_fee_once() {
+    // 10% of 45 is 4.5 and 3% is 1.35: `Ceil` charges 5 + 2, this mode burns 4 and charges ceil(5.85) - 4 = 2
+    let multi_send = || MultiSend::new(vec![balance("account1", "45denom1")], vec![balance("account_recipient", "45denom1")]).unwrap();
+    let definition = |rounding| DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(3)).with_rounding(rounding);
+
+    let receipt = receipt_for(vec![balance("account1", "100denom1")], vec![definition(RoundingMode::Ceil)], multi_send());
+    assert_eq!(receipt.debits.iter().map(|debit| debit.gross).collect::<Vec<_>>(), amounts(&[52]));
+
+    let receipt = receipt_for(vec![balance("account1", "100denom1")], vec![definition(RoundingMode::FloorRemainderToIssuer)], multi_send());
+    assert_eq!(receipt.changes, vec![
+      change("account1", "-51denom1"),
+      change("account_recipient", "45denom1"),
+      change("issuer_account_A", "2denom1"),
+    ]);
+    assert_eq!(receipt.burned.to_string(), "4denom1");
+
+    // the cut off part is commission like the rest, so the recipients share it
+    let definitions = vec![definition(RoundingMode::FloorRemainderToIssuer).with_commission_recipients(&[("treasury", 1), ("referrer", 1)])];
+    let receipt = receipt_for(vec![balance("account1", "100denom1")], definitions, multi_send());
+    assert_eq!(receipt.changes, vec![
+      change("account1", "-51denom1"),
+      change("account_recipient", "45denom1"),
+      change("referrer", "1denom1"),
+      change("treasury", "1denom1"),
+    ]);
+  }
+
+  #[test]
+  fn check_half_even_rounds_ties_to_even_shares() {
+    // 10% of 45 and of 55 are both ties: 4.5 rounds to 4 and 5.5 rounds to 6
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::zero()).with_rounding(RoundingMode::HalfEven)];
+    let orig_balances = vec![balance("account1", "100denom1"), balance("account2", "100denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "45denom1"), balance("account2", "55denom1")],
+      vec![balance("account_recipient", "100denom1")],
+    ).unwrap();
+
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.debits.iter().map(|debit| debit.burn).collect::<Vec<_>>(), amounts(&[4, 6]));
+    assert_eq!(receipt.burned.to_string(), "10denom1");
+  }
+
+  #[test]
+  fn check_largest_remainder_burns_the_rounded_total_once() {
+    // test case 5: 1% of the 2 units transferred is rounded up to 1 burnt and 1 commission for the whole denom,
+    // not per sender; both senders have the same remainder, so the unit goes to the smaller address
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(1), Decimal::percent(1)).with_rounding(RoundingMode::LargestRemainder)];
+    let orig_balances = vec![balance("account1", "1000denom1"), balance("account2", "1000denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "1denom1"), balance("account2", "1denom1")],
+      vec![balance("account_recipient", "2denom1")],
+    ).unwrap();
+
+    let expected_result = vec![
+      change("account1", "-3denom1"),
+      change("account2", "-1denom1"),
+      change("account_recipient", "2denom1"),
+      change("issuer_account_A", "1denom1"),
+    ];
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.changes, expected_result);
+    assert_eq!(receipt.burned.to_string(), "1denom1");
+    assert_eq!(receipt.commission.to_string(), "1denom1");
+  }
+
+  #[test]
+  fn check_largest_remainder_shares_sum_to_the_total() {
+    // ten senders of 1 unit each and one of 7: 1% of 17 rounds up to a total of 1, which the sender of 7 pays
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(1), Decimal::zero()).with_rounding(RoundingMode::LargestRemainder)];
+    let senders: Vec<String> = (0..10).map(|i| format!("account{}", i)).collect();
+    let mut inputs: Vec<Balance> = senders.iter().map(|address| balance(address, "1denom1")).collect();
+    inputs.push(balance("big_sender", "7denom1"));
+    let mut orig_balances: Vec<Balance> = senders.iter().map(|address| balance(address, "1denom1")).collect();
+    orig_balances.push(balance("big_sender", "8denom1"));
+    let multi_send = MultiSend::new(inputs, vec![balance("account_recipient", "17denom1")]).unwrap();
+
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.burned.to_string(), "1denom1");
+    let payers: Vec<&str> = receipt.debits.iter().filter(|debit| debit.burn != ZERO).map(|debit| debit.address.as_str()).collect();
+    assert_eq!(payers, vec!["big_sender"]);
+  }
+
+  #[test]
+  fn check_fee_limits_bound_each_account_share() {
+    // 10% burn capped at 5 per sender, and at least 2 commission per sender although the rate is zero
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::zero()).with_max_burn(amount(5)).with_min_commission(amount(2))
+    ];
+    // account1 could not afford the uncapped burn of 10
+    let orig_balances = vec![balance("account1", "107denom1"), balance("account2", "35denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "100denom1"), balance("account2", "30denom1")],
+      vec![balance("account_recipient", "130denom1")],
+    ).unwrap();
+
+    let expected_result = vec![
+      change("account1", "-107denom1"),
+      change("account2", "-35denom1"),
+      change("account_recipient", "130denom1"),
+      change("issuer_account_A", "4denom1"),
+    ];
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.changes, expected_result);
+    assert_eq!(receipt.debits.iter().map(|debit| (debit.burn, debit.commission)).collect::<Vec<_>>(), pairs(&[(5, 2), (3, 2)]));
+    assert_eq!(receipt.burned.to_string(), "8denom1");
+  }
+
+  #[test]
+  fn check_fee_limits_bound_the_tx_total() {
+    // the 15 burnt without a cap is capped at 10 for the whole tx and split 6.67 / 3.33
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(1))
+        .with_max_burn(amount(10))
+        .with_min_commission(amount(5))
+        .with_fee_limit_scope(FeeLimitScope::PerTx)
+    ];
+    let orig_balances = vec![balance("account1", "1000denom1"), balance("account2", "1000denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "100denom1"), balance("account2", "50denom1")],
+      vec![balance("account_recipient", "150denom1")],
+    ).unwrap();
+
+    // the commission of 1 + 1 is raised to 5 and split 3.33 / 1.67, the left over unit going to account2
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.debits.iter().map(|debit| (debit.burn, debit.commission)).collect::<Vec<_>>(), pairs(&[(7, 3), (3, 2)]));
+    assert_eq!(receipt.burned.to_string(), "10denom1");
+    assert_eq!(receipt.commission.to_string(), "5denom1");
+  }
+
+  #[test]
+  fn check_tx_wide_shares_scale_to_many_senders() {
+    // 3000 senders of 1 to 7 units, 11994 in total: the 10% burn of 1199.4 is capped at 100 for the whole tx and
+    // the 1% commission of 119.94 rounded up to 120, each spread over every sender
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(1))
+        .with_rounding(RoundingMode::LargestRemainder)
+        .with_max_burn(amount(100))
+        .with_fee_limit_scope(FeeLimitScope::PerTx)
+    ];
+    let senders: Vec<String> = (0..3000).map(|i| format!("account{}", i)).collect();
+    let inputs: Vec<Balance> = senders.iter().enumerate().map(|(i, address)| balance(address, &format!("{}denom1", i % 7 + 1))).collect();
+    let orig_balances: Vec<Balance> = senders.iter().map(|address| balance(address, "100denom1")).collect();
+    let multi_send = MultiSend::new(inputs, vec![balance("account_recipient", "11994denom1")]).unwrap();
+
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.burned.to_string(), "100denom1");
+    assert_eq!(receipt.commission.to_string(), "120denom1");
+    assert_eq!(receipt.debits.len(), 3000);
+  }
+
+  #[test]
+  fn check_minimum_fee_needs_a_transfer_between_non_issuers() {
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero()).with_min_burn(amount(3))];
+    let orig_balances = vec![balance("account1", "100denom1"), balance("issuer_account_A", "100denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "100denom1"), balance("issuer_account_A", "100denom1")],
+      vec![balance("issuer_account_A", "100denom1"), balance("account_recipient", "100denom1")],
+    ).unwrap();
+    // 100 units reach a non-issuer, so account1 owes the minimum burn and cannot afford it
+    assert!(matches!(calculate_balance_changes(orig_balances, definitions.clone(), multi_send), Err(MultiSendError::InsufficientBalance { .. })));
+
+    // sending everything to the issuer transfers nothing between non-issuers, so no minimum applies
+    let multi_send = MultiSend::new(vec![balance("account1", "100denom1")], vec![balance("issuer_account_A", "100denom1")]).unwrap();
+    let receipt = receipt_for(vec![balance("account1", "100denom1")], definitions, multi_send);
+    assert!(receipt.burned.is_empty());
+  }
+
+  #[test]
+  fn check_tiered_commission_drops_with_transfer_size() {
+    // 2% below 1000, 1% from 1000 and 0.5% from 100000
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::percent(2)).with_commission_tiers(vec![
+        FeeTier { from: amount(100_000), rate: Decimal::permille(5) },
+        FeeTier { from: amount(1_000), rate: Decimal::percent(1) },
+      ])
+    ];
+
+    for (sent, commission) in [(500, 10), (999, 20), (1_000, 10), (50_000, 500), (100_000, 500), (200_000, 1_000)] {
+      let coins = format!("{}denom1", sent);
+      let multi_send = MultiSend::new(vec![balance("account1", &coins)], vec![balance("account_recipient", &coins)]).unwrap();
+      let receipt = receipt_for(vec![balance("account1", "1000000denom1")], definitions.clone(), multi_send);
+      assert_eq!(receipt.commission.amount_of("denom1"), amount(commission), "sending {}", sent);
+    }
+  }
+
+  #[test]
+  fn check_tier_is_chosen_by_non_issuer_transferred_amount() {
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(2), Decimal::zero())
+        .with_burn_tiers(vec![FeeTier { from: amount(1_000), rate: Decimal::percent(1) }])
+    ];
+    // 2000 are sent but only 500 reach a non-issuer, so the 2% tier applies to those 500
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "2000denom1")],
+      vec![balance("issuer_account_A", "1500denom1"), balance("account_recipient", "500denom1")],
+    ).unwrap();
+    let receipt = receipt_for(vec![balance("account1", "3000denom1")], definitions, multi_send);
+    assert_eq!(receipt.burned.to_string(), "10denom1");
+  }
+
+  #[test]
+  fn check_exempt_addresses_neither_pay_nor_count_towards_fees() {
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(10)).with_exempt_addresses(&["exchange", "bridge_escrow"])
+    ];
+    let orig_balances = vec![balance("account1", "1000denom1"), balance("exchange", "1000denom1")];
+    // the exchange sends for free; of account1's 300 only the 100 that reach account_recipient pay fees
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "300denom1"), balance("exchange", "500denom1")],
+      vec![balance("bridge_escrow", "600denom1"), balance("account_recipient", "100denom1"), balance("exchange", "100denom1")],
+    ).unwrap();
+
+    let expected_result = vec![
+      change("account1", "-320denom1"),
+      change("account_recipient", "100denom1"),
+      change("bridge_escrow", "600denom1"),
+      change("exchange", "-400denom1"),
+      change("issuer_account_A", "10denom1"),
+    ];
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.changes, expected_result);
+    assert_eq!(receipt.burned.to_string(), "10denom1");
+  }
+
+  #[test]
+  fn check_commission_is_split_between_weighted_recipients() {
+    // 10% commission of 85 is 9 (8.5 rounded up), split 5:3:2 into 4.5 / 2.7 / 1.8
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::percent(10))
+        .with_commission_recipients(&[("treasury", 5), ("community_pool", 3), ("referrer", 2), ("nobody", 0)])
+    ];
+    let orig_balances = vec![balance("account1", "100denom1")];
+    let multi_send = MultiSend::new(vec![balance("account1", "85denom1")], vec![balance("account_recipient", "85denom1")]).unwrap();
+
+    let expected_result = vec![
+      change("account1", "-94denom1"),
+      change("account_recipient", "85denom1"),
+      change("community_pool", "3denom1"),
+      change("referrer", "2denom1"),
+      change("treasury", "4denom1"),
+    ];
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.changes, expected_result);
+    let credits: Vec<(&str, Amount)> = receipt.commission_credits.iter().map(|credit| (credit.recipient.as_str(), credit.amount)).collect();
+    assert_eq!(credits, vec![("community_pool", amount(3)), ("referrer", amount(2)), ("treasury", amount(4))]);
+    assert_eq!(receipt.commission.to_string(), "9denom1");
+  }
+
+  #[test]
+  fn check_invalid_commission_recipients_are_rejected() {
+    let multi_send = || MultiSend::new(vec![balance("account1", "85denom1")], vec![balance("account_recipient", "85denom1")]).unwrap();
+    let definition = || DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::percent(10));
+    let cases = [
+      (definition().with_commission_recipients(&[("treasury", 1), ("", 1)]), MultiSendError::EmptyAddress),
+      (DenomDefinition::new("denom1", "", Decimal::zero(), Decimal::percent(10)), MultiSendError::EmptyAddress),
+      (definition().with_commission_recipients(&[("treasury", 0), ("referrer", 0)]), MultiSendError::ZeroCommissionWeights { denom: "denom1".to_string() }),
+    ];
+    for (definition, error) in cases {
+      assert_eq!(calculate_balance_changes(vec![balance("account1", "100denom1")], vec![definition], multi_send()), Err(error));
+    }
+  }
+
+  #[test]
+  fn check_fees_are_paid_in_the_fee_denom() {
+    // 0.5% burn and 1% commission of a usdt transfer, paid in core at 1.5 core per usdt
+    let definitions = vec![
+      DenomDefinition::new("usdt", "issuer_account_A", Decimal::permille(5), Decimal::percent(1))
+        .with_fee_denom("core", Decimal::from_ratio(3, 2).unwrap()),
+      DenomDefinition::new("core", "issuer_account_B", Decimal::zero(), Decimal::zero()),
+    ];
+    let multi_send = || MultiSend::new(vec![balance("account1", "1000usdt")], vec![balance("account_recipient", "1000usdt")]).unwrap();
+
+    // a burn of 5 and a commission of 10 usdt cost 7.5 (rounded up to 8) and 15 core
+    let result = calculate_balance_changes(vec![balance("account1", "22core,1000usdt")], definitions.clone(), multi_send());
+    assert_eq!(result, Err(MultiSendError::InsufficientBalance { address: "account1".to_string(), denom: "core".to_string(), required: amount(23), available: amount(22) }));
+
+    let receipt = receipt_for(vec![balance("account1", "23core,1000usdt")], definitions, multi_send());
+    let expected_result = vec![
+      change("account1", "-23core,-1000usdt"),
+      change("account_recipient", "1000usdt"),
+      change("issuer_account_A", "15core"),
+    ];
+    assert_eq!(receipt.changes, expected_result);
+    let debit = receipt.debit_of("account1", "usdt").unwrap();
+    assert_eq!((debit.fee_denom.as_str(), debit.burn, debit.commission, debit.gross), ("core", amount(8), amount(15), amount(1000)));
+    assert_eq!((receipt.burned.to_string(), receipt.commission.to_string()), ("8core".to_string(), "15core".to_string()));
+  }
+
+  #[test]
+  fn check_largest_remainder_converts_the_total_to_the_fee_denom_once() {
+    // 1% of 3 x 50usdt is a burn of 2usdt for the denom, which costs 1core at 1/3 core per usdt; converting the two
+    // senders' 1usdt shares one by one would round each up to 1core
+    let definitions = vec![
+      DenomDefinition::new("usdt", "issuer_account_A", Decimal::percent(1), Decimal::zero())
+        .with_rounding(RoundingMode::LargestRemainder)
+        .with_fee_denom("core", Decimal::from_ratio(1, 3).unwrap()),
+      DenomDefinition::new("core", "issuer_account_B", Decimal::zero(), Decimal::zero()),
+    ];
+    let orig_balances = vec![balance("account1", "10core,50usdt"), balance("account2", "10core,50usdt"), balance("account3", "10core,50usdt")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "50usdt"), balance("account2", "50usdt"), balance("account3", "50usdt")],
+      vec![balance("account_recipient", "150usdt")],
+    ).unwrap();
+
+    let receipt = receipt_for(orig_balances, definitions, multi_send);
+    assert_eq!(receipt.debits.iter().map(|debit| debit.burn).collect::<Vec<_>>(), amounts(&[1, 0, 0]));
+    assert_eq!(receipt.burned.to_string(), "1core");
+  }
+
+  #[test]
+  fn check_gross_up_is_the_least_sufficient_balance() {
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12)),
+      DenomDefinition::new("denom2", "issuer_account_B", Decimal::permille(15), Decimal::zero()).with_rounding(RoundingMode::HalfEven),
+    ];
+    let outputs = || vec![balance("account_recipient", "1000denom1,333denom2"), balance("issuer_account_A", "500denom1")];
+
+    // only the 1000 that reach a non-issuer pay fees; 1.5% of 333 is 4.995, rounded to 5
+    let gross = gross_up("account1", outputs(), definitions.clone()).unwrap();
+    assert_eq!(gross.to_string(), "1700denom1,338denom2");
+
+    let multi_send = || MultiSend::new(vec![balance("account1", "1500denom1,333denom2")], outputs()).unwrap();
+    assert!(calculate_balance_changes(vec![balance("account1", "1700denom1,338denom2")], definitions.clone(), multi_send()).is_ok());
+    let result = calculate_balance_changes(vec![balance("account1", "1700denom1,337denom2")], definitions, multi_send());
+    assert!(matches!(result, Err(MultiSendError::InsufficientBalance { required, .. }) if required == amount(338)));
+  }
+
+  #[test]
+  fn check_gross_up_debits_match_the_executed_tx() {
+    for rounding in [RoundingMode::Ceil, RoundingMode::HalfEven, RoundingMode::LargestRemainder] {
+      let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(10), Decimal::percent(3)).with_rounding(rounding)];
+      let multi_send = || MultiSend::new(
+        vec![balance("account1", "60denom1"), balance("account2", "90denom1"), balance("issuer_account_A", "25denom1")],
+        vec![balance("account_recipient1", "50denom1"), balance("issuer_account_A", "100denom1"), balance("account_recipient2", "25denom1")],
+      ).unwrap();
+
+      let debits = gross_up_debits(definitions.clone(), &multi_send()).unwrap();
+      let orig_balances: Vec<Balance> = debits.iter().map(|debit| balance(&debit.address, &format!("{}denom1", debit.gross))).collect();
+      let receipt = receipt_for(orig_balances, definitions, multi_send());
+      assert_eq!(debits, receipt.debits, "{:?}", rounding);
+    }
+  }
+
+  #[test]
+  fn check_max_sendable_at_rounding_boundaries() {
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12))];
+    let max = |balance_amount: &str, definitions: &[DenomDefinition]| {
+      max_sendable(&[balance("account1", balance_amount)], definitions, "account1", "denom1", "account_recipient").unwrap()
+    };
+    // 1000 + 80 + 120 = 1200; 999 + ceil(79.92) + ceil(119.88) = 1199; 1001 + ceil(80.08) + ceil(120.12) = 1203
+    assert_eq!(max("1200denom1", &definitions), amount(1000));
+    assert_eq!(max("1199denom1", &definitions), amount(999));
+    assert_eq!(max("1202denom1", &definitions), amount(1000));
+    assert_eq!(max("1203denom1", &definitions), amount(1001));
+    assert_eq!(max("1denom1", &definitions), amount(0));
+    assert_eq!(max("3denom1", &definitions), amount(1));
+
+    // rounding down lets 1001 + 80 + 120 fit in 1201
+    let floor = vec![definitions[0].clone().with_rounding(RoundingMode::Floor)];
+    assert_eq!(max("1201denom1", &floor), amount(1001));
+
+    // the issuer takes no fee, so the whole balance can be sent to it
+    let to_issuer = max_sendable(&[balance("account1", "1200denom1")], &definitions, "account1", "denom1", "issuer_account_A").unwrap();
+    assert_eq!(to_issuer, amount(1200));
+  }
+
+  #[test]
+  fn check_max_sendable_at_the_amount_limit() {
+    // a balance of Amount::MAX covers 1.2 times the principal at 8% + 12%; the gross debit of a larger candidate
+    // does not fit in an amount, which makes it unaffordable rather than an error
+    let held = format!("{}denom1", Amount::MAX);
+    let (expected, _) = amount::mul_div_rem(Amount::MAX, amount(5), amount(6)).unwrap();
+    let max = max_sendable(&[balance("account1", &held)], &denom1_definition(), "account1", "denom1", "account_recipient");
+    assert_eq!(max, Ok(expected));
+  }
+
+  #[test]
+  fn check_max_sendable_searches_every_tier() {
+    // 2% below 1000 and 1% from 1000: 1000 costs 1010 but 999 costs 1019
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(2), Decimal::zero())
+        .with_burn_tiers(vec![FeeTier { from: amount(1_000), rate: Decimal::percent(1) }])
+    ];
+    let max = |balance_amount: &str| max_sendable(&[balance("account1", balance_amount)], &definitions, "account1", "denom1", "account_recipient").unwrap();
+    assert_eq!(max("1012denom1"), amount(1001));
+    assert_eq!(max("1010denom1"), amount(1000));
+    assert_eq!(max("1009denom1"), amount(989));
+  }
+
+  #[test]
+  fn check_max_sendable_matches_a_linear_scan() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    for _ in 0..50 {
+      let definitions = random_definitions(&mut rng);
+      let held = 1 + rng.below(400);
+      let orig_balances = [balance("account1", &format!("{}denom1,{}denom2", held, 1 + rng.below(50)))];
+      let expected = (1..=held).rev()
+        .find(|&principal| {
+          let multi_send = MultiSend::new(
+            vec![balance("account1", &format!("{}denom1", principal))],
+            vec![balance("account_recipient", &format!("{}denom1", principal))],
+          ).unwrap();
+          calculate_balance_changes(vec![balance("account1", &orig_balances[0].coins.to_string())], definitions.clone(), multi_send).is_ok()
+        })
+        .unwrap_or(0);
+      assert_eq!(max_sendable(&orig_balances, &definitions, "account1", "denom1", "account_recipient"), Ok(amount(expected.into())), "{:?}", definitions);
+    }
+  }
+
+  #[test]
+  fn check_max_sendable_rejects_unknown_account_and_denom() {
+    let definitions = denom1_definition();
+    let orig_balances = [balance("account1", "100denom1")];
+    assert_eq!(max_sendable(&orig_balances, &definitions, "account9", "denom1", "account2"), Err(MultiSendError::UnknownAccount { address: "account9".to_string() }));
+    assert_eq!(max_sendable(&orig_balances, &definitions, "account1", "denom9", "account2"), Err(MultiSendError::UnknownDenom { denom: "denom9".to_string() }));
+  }
+
+  #[test]
+  fn check_rates_outside_zero_to_one_are_rejected() {
+    let multi_send = || MultiSend::new(vec![balance("account1", "100denom1")], vec![balance("account_recipient", "100denom1")]).unwrap();
+    let cases = [
+      (DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(-1), Decimal::zero()), Decimal::percent(-1)),
+      (DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::percent(101)), Decimal::percent(101)),
+      (DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero())
+        .with_burn_tiers(vec![FeeTier { from: amount(10), rate: Decimal::permille(-5) }]), Decimal::permille(-5)),
+      (DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero()).with_fee_denom("core", Decimal::percent(-200)), Decimal::percent(-200)),
+      (DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero()).with_fee_denom("core", Decimal::zero()), Decimal::zero()),
+    ];
+    for (definition, rate) in cases {
+      let result = calculate_balance_changes(vec![balance("account1", "1000denom1")], vec![definition], multi_send());
+      assert_eq!(result, Err(MultiSendError::InvalidRate { denom: "denom1".to_string(), rate }));
+    }
+
+    // the fee denom itself has to be a valid denom
+    for fee_denom in ["", "1core"] {
+      let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::zero(), Decimal::zero()).with_fee_denom(fee_denom, Decimal::one())];
+      let result = calculate_balance_changes(vec![balance("account1", "1000denom1")], definitions, multi_send());
+      assert_eq!(result, Err(MultiSendError::InvalidDenom { denom: fee_denom.to_string() }));
+    }
+
+    // a rate of exactly 1 is allowed: the sender pays the amount again as burn
+    let definitions = vec![DenomDefinition::new("denom1", "issuer_account_A", Decimal::one(), Decimal::zero())];
+    assert!(calculate_balance_changes(vec![balance("account1", "200denom1")], definitions, multi_send()).is_ok());
+  }
+
+  #[test]
+  fn check_duplicate_definitions_are_rejected() {
+    let definitions = vec![
+      DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(1), Decimal::zero()),
+      DenomDefinition::new("denom1", "issuer_account_B", Decimal::zero(), Decimal::zero()),
+    ];
+    let multi_send = MultiSend::new(vec![balance("account1", "100denom1")], vec![balance("account_recipient", "100denom1")]).unwrap();
+    let result = calculate_balance_changes(vec![balance("account1", "1000denom1")], definitions, multi_send);
+    assert_eq!(result, Err(MultiSendError::DuplicateDefinition { denom: "denom1".to_string() }));
+  }
+
+  #[test]
+  fn check_issuer_pays_no_commission_to_itself() {
+    let orig_balances = vec![
+      balance("account1", "1000denom1"),
+      balance("issuer_account_A", "500denom1"),
+    ];
+
+    let multi_send = MultiSend::new(
+      vec![
+        balance("account1", "500denom1"),
+        balance("issuer_account_A", "500denom1"),
+      ],
+      vec![balance("account_recipient", "1000denom1")],
+    ).unwrap();
+
+    // only account1 pays: 8% burn and 12% commission of its 500
+    let expected_result = vec![
+      change("account1", "-600denom1"),
+      change("account_recipient", "1000denom1"),
+      change("issuer_account_A", "-440denom1"),
+    ];
+
+    let result = calculate_balance_changes(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(result.unwrap(), expected_result);
+  }
+
+  #[test]
+  fn check_output_is_sorted_and_identical_across_runs() {
+    let run = || {
+      let definitions = vec![
+        DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12)),
+        DenomDefinition::new("denom2", "issuer_account_B", Decimal::percent(1), Decimal::percent(2))
+      ];
+      let orig_balances = vec![
+        balance("account3", "1000denom1,1000denom2"),
+        balance("account1", "1000denom1,1000denom2"),
+        balance("account2", "1000denom1,1000denom2"),
+      ];
+      let multi_send = MultiSend::new(
+        vec![
+          balance("account3", "100denom2,100denom1"),
+          balance("account1", "300denom1"),
+          balance("account2", "200denom2"),
+        ],
+        vec![
+          balance("account_recipient_z", "250denom1,150denom2"),
+          balance("account_recipient_a", "150denom1,150denom2"),
+        ],
+      ).unwrap();
+      let changes = calculate_balance_changes(orig_balances, definitions, multi_send).unwrap();
+      changes.iter().map(|balance| balance.to_string()).collect::<Vec<String>>().join(";")
+    };
+
+    let serialized = run();
+    assert_eq!(serialized, [
+      "account1:-360denom1",
+      "account2:-206denom2",
+      "account3:-120denom1,-103denom2",
+      "account_recipient_a:150denom1,150denom2",
+      "account_recipient_z:250denom1,150denom2",
+      "issuer_account_A:48denom1",
+      "issuer_account_B:6denom2",
+    ].join(";"));
+    for _ in 0..50 {
+      assert_eq!(run(), serialized);
+    }
+  }
+
+  #[test]
+  fn check_supply_invariant_detects_created_coins() {
+    let changes = BTreeMap::from([
+      ("account1".to_string(), "-108denom1".parse::<SignedCoins>().unwrap()),
+      ("account_recipient".to_string(), "100denom1".parse::<SignedCoins>().unwrap()),
+    ]);
+    assert_eq!(assert_supply_only_changes_by_burn(&changes, &"8denom1".parse().unwrap()), Ok(()));
+    assert_eq!(assert_supply_only_changes_by_burn(&changes, &"7denom1".parse().unwrap()),
+      Err(MultiSendError::SupplyMismatch { denom: "denom1".to_string(), delta: SignedAmount::negative(amount(8)), burned: amount(7) }));
+    assert_eq!(assert_supply_only_changes_by_burn(&changes, &"8denom1,1denom2".parse().unwrap()),
+      Err(MultiSendError::SupplyMismatch { denom: "denom2".to_string(), delta: SignedAmount::default(), burned: amount(1) }));
+  }
+
+  fn receipt_for(orig_balances: Vec<Balance>, definitions: Vec<DenomDefinition>, multi_send: MultiSend) -> Receipt {
+    let token_map = definitions.into_iter().map(|def| (def.denom.clone(), def)).collect();
+    let account_map = orig_balances.into_iter().map(|balance| (balance.address.clone(), balance.coins)).collect();
+    compute_balance_changes(&account_map, &token_map, &multi_send).unwrap()
+  }
+
+  #[test]
+  fn check_receipt_itemizes_fees() {
+    let orig_balances = vec![balance("account1", "2000denom1"), balance("account2", "1000denom1"), balance("issuer_account_A", "100denom1")];
+    let multi_send = MultiSend::new(
+      vec![balance("account1", "1000denom1"), balance("account2", "500denom1"), balance("issuer_account_A", "100denom1")],
+      vec![balance("account_recipient", "1600denom1")],
+    ).unwrap();
+
+    let receipt = receipt_for(orig_balances, denom1_definition(), multi_send);
+    assert_eq!(receipt.debit_of("account1", "denom1"), Some(&FeeDebit {
+      address: "account1".to_string(),
+      denom: "denom1".to_string(),
+      principal: amount(1000),
+      fee_denom: "denom1".to_string(),
+      burn: amount(80),
+      commission: amount(120),
+      gross: amount(1200),
+    }));
+    assert_eq!(receipt.debits.iter().map(|debit| (debit.address.as_str(), debit.burn, debit.commission, debit.gross)).collect::<Vec<_>>(),
+      vec![("account1", amount(80), amount(120), amount(1200)), ("account2", amount(40), amount(60), amount(600)),
+        ("issuer_account_A", ZERO, ZERO, amount(100))]);
+    assert_eq!(receipt.commission_credits, vec![
+      CommissionCredit { recipient: "issuer_account_A".to_string(), denom: "denom1".to_string(), fee_denom: "denom1".to_string(), amount: amount(180) },
+    ]);
+    assert_eq!(receipt.burned, "120denom1".parse().unwrap());
+    assert_eq!(receipt.commission, "180denom1".parse().unwrap());
+
+    // the itemized lists add up to the net changes
+    for change in &receipt.changes {
+      for coin in &change.coins {
+        let debited = receipt.debits.iter().filter(|d| d.address == change.address && d.denom == coin.denom)
+          .try_fold(ZERO, |sum, d| sum.checked_add(d.gross)).unwrap();
+        let credited = receipt.commission_credits.iter().filter(|c| c.recipient == change.address && c.fee_denom == coin.denom)
+          .try_fold(ZERO, |sum, c| sum.checked_add(c.amount)).unwrap();
+        let received = if change.address == "account_recipient" { amount(1600) } else { ZERO };
+        let net = SignedAmount::positive(received.checked_add(credited).unwrap()).checked_sub(SignedAmount::positive(debited)).unwrap();
+        assert_eq!(coin.amount, net, "{}", change);
+      }
+    }
+  }
+}
+
@@ -0,0 +1,62 @@
+use crate::amount::Amount;
+use crate::coins::Coins;
+use crate::BalanceChange;
+
+// What one input address paid for sending one denom: `principal` is what the outputs receive, and `burn` and
+// `commission` are paid in `fee_denom`. `gross` is the amount of `denom` that left the account: `principal` plus
+// the fees when they are paid in the transferred denom itself, the usual case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeDebit {
+  pub address: String,
+  pub denom: String,
+  pub principal: Amount,
+  pub fee_denom: String,
+  pub burn: Amount,
+  pub commission: Amount,
+  pub gross: Amount,
+}
+
+impl FeeDebit {
+  // (denom, amount) of every coin that left the account, or None if the fees in a separate fee denom overflow
+  pub fn spent(&self) -> Option<Vec<(&str, Amount)>> {
+    if self.fee_denom == self.denom {
+      return Some(vec![(&self.denom, self.gross)]);
+    }
+    Some(vec![(&self.denom, self.gross), (&self.fee_denom, self.burn.checked_add(self.commission)?)])
+  }
+}
+
+// Commission on transfers of `denom` credited to `recipient`, summed over all paying inputs. `amount` is paid in
+// `fee_denom`, which several denoms may share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommissionCredit {
+  pub recipient: String,
+  pub denom: String,
+  pub fee_denom: String,
+  pub amount: Amount,
+}
+
+// Outcome of a `MultiSend`, as computed by `calculate_balance_changes` and committed by `Bank::execute`.
+// The itemized lists explain `changes`: for every address, its change is what it received as output or
+// commission credit minus its gross debits.
+#[derive(Debug)]
+pub struct Receipt {
+  // net change per address (negative means deduction, positive means addition), sorted by address and then by denom
+  pub changes: Vec<BalanceChange>,
+  // one entry per input address and denom, sorted by address and then by denom
+  pub debits: Vec<FeeDebit>,
+  // one entry per commission recipient and transferred denom with a non-zero credit, sorted by recipient and then by
+  // denom
+  pub commission_credits: Vec<CommissionCredit>,
+  // total burned per denom the fees are paid in; the changes of each denom add up to exactly minus this amount
+  pub burned: Coins,
+  // total commission credited per denom the fees are paid in: a denom with a separate fee denom adds its commission
+  // to that of the fee denom; `commission_credits` tell the transferred denoms apart
+  pub commission: Coins,
+}
+
+impl Receipt {
+  pub fn debit_of(&self, address: &str, denom: &str) -> Option<&FeeDebit> {
+    self.debits.iter().find(|debit| debit.address == address && debit.denom == denom)
+  }
+}
@@ -0,0 +1,43 @@
+// Fixtures shared by the tests of every module. Coins are written as cosmos coin strings, e.g.
+// balance("account1", "650denom1"), and bare amounts go through `amount`, so the same fixtures work with either
+// amount type.
+use crate::amount::Amount;
+use crate::bank::Bank;
+use crate::coins::{Coins, SignedCoins};
+use crate::decimal::Decimal;
+use crate::{Balance, BalanceChange, DenomDefinition};
+
+// the identity without the `uint256` feature
+#[allow(clippy::useless_conversion)]
+pub fn amount(value: u128) -> Amount {
+  Amount::from(value)
+}
+
+pub fn amounts(values: &[u128]) -> Vec<Amount> {
+  values.iter().map(|value| amount(*value)).collect()
+}
+
+pub fn balance(address: &str, coins: &str) -> Balance {
+  Balance { address: address.to_string(), coins: coins.parse().unwrap() }
+}
+
+pub fn change(address: &str, coins: &str) -> BalanceChange {
+  BalanceChange { address: address.to_string(), coins: coins.parse().unwrap() }
+}
+
+pub fn coins(coins: &str) -> Coins {
+  coins.parse().unwrap()
+}
+
+pub fn signed_coins(coins: &str) -> SignedCoins {
+  coins.parse().unwrap()
+}
+
+// denom1 with an 8% burn and 12% commission, and fee-free denom2
+pub fn bank() -> Bank {
+  let definitions = vec![
+    DenomDefinition::new("denom1", "issuer_account_A", Decimal::percent(8), Decimal::percent(12)),
+    DenomDefinition::new("denom2", "issuer_account_B", Decimal::zero(), Decimal::zero()),
+  ];
+  Bank::new(vec![balance("account1", "1000denom1,1000denom2"), balance("account2", "500denom1")], definitions).unwrap()
+}
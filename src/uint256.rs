@@ -0,0 +1,241 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// 256-bit unsigned integer, the `Amount` of the `uint256` feature. Stored as four 64-bit limbs, least significant
+// first. Only the checked operations the amount math needs are provided.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Uint256([u64; 4]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseUint256Error {
+  pub input: String,
+}
+
+impl fmt::Display for ParseUint256Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "invalid 256-bit unsigned integer {:?}", self.input)
+  }
+}
+
+impl std::error::Error for ParseUint256Error {}
+
+impl Uint256 {
+  pub const ZERO: Uint256 = Uint256([0; 4]);
+  pub const MAX: Uint256 = Uint256([u64::MAX; 4]);
+
+  pub fn checked_add(self, other: Uint256) -> Option<Uint256> {
+    let mut result = [0u64; 4];
+    let mut carry = false;
+    for (i, limb) in result.iter_mut().enumerate() {
+      let (sum, overflow1) = self.0[i].overflowing_add(other.0[i]);
+      let (sum, overflow2) = sum.overflowing_add(carry as u64);
+      *limb = sum;
+      carry = overflow1 || overflow2;
+    }
+    if carry { None } else { Some(Uint256(result)) }
+  }
+
+  pub fn checked_sub(self, other: Uint256) -> Option<Uint256> {
+    if self < other {
+      return None;
+    }
+    Some(self.wrapping_sub(other))
+  }
+
+  pub fn checked_mul(self, other: Uint256) -> Option<Uint256> {
+    let product = widening_mul(self, other);
+    if product[4..].iter().any(|&limb| limb != 0) {
+      return None;
+    }
+    Some(Uint256([product[0], product[1], product[2], product[3]]))
+  }
+
+  // (floor(self * b / c), self * b mod c) over the full 512-bit product, or None if c is zero or the quotient does
+  // not fit in 256 bits
+  pub fn mul_div_rem(self, b: Uint256, c: Uint256) -> Option<(Uint256, Uint256)> {
+    if c == Uint256::ZERO {
+      return None;
+    }
+    let product = widening_mul(self, b);
+    let hi = Uint256([product[4], product[5], product[6], product[7]]);
+    if hi >= c {
+      return None;
+    }
+    // schoolbook long division of the low half, one bit at a time, starting from the high half as remainder
+    let mut rem = hi;
+    let mut quot = Uint256::ZERO;
+    for i in (0..256).rev() {
+      let carry = rem.shl1(product[i / 64] >> (i % 64) & 1);
+      quot.shl1(0);
+      if carry || rem >= c {
+        rem = rem.wrapping_sub(c);
+        quot.0[0] |= 1;
+      }
+    }
+    Some((quot, rem))
+  }
+
+  pub fn to_u128(self) -> Option<u128> {
+    if self.0[2] != 0 || self.0[3] != 0 {
+      return None;
+    }
+    Some(self.0[0] as u128 | (self.0[1] as u128) << 64)
+  }
+
+  fn wrapping_sub(self, other: Uint256) -> Uint256 {
+    let mut result = [0u64; 4];
+    let mut borrow = false;
+    for (i, limb) in result.iter_mut().enumerate() {
+      let (diff, borrow1) = self.0[i].overflowing_sub(other.0[i]);
+      let (diff, borrow2) = diff.overflowing_sub(borrow as u64);
+      *limb = diff;
+      borrow = borrow1 || borrow2;
+    }
+    Uint256(result)
+  }
+
+  // shifts left by one bit, shifting `bit` in at the bottom; returns the bit shifted out at the top
+  fn shl1(&mut self, bit: u64) -> bool {
+    let mut carry = bit;
+    for limb in self.0.iter_mut() {
+      let next = *limb >> 63;
+      *limb = (*limb << 1) | carry;
+      carry = next;
+    }
+    carry == 1
+  }
+
+  fn div_rem_u64(self, divisor: u64) -> (Uint256, u64) {
+    let mut quot = [0u64; 4];
+    let mut rem = 0u128;
+    for i in (0..4).rev() {
+      let current = (rem << 64) | self.0[i] as u128;
+      quot[i] = (current / divisor as u128) as u64;
+      rem = current % divisor as u128;
+    }
+    (Uint256(quot), rem as u64)
+  }
+}
+
+// full 512-bit product as eight limbs, least significant first
+fn widening_mul(a: Uint256, b: Uint256) -> [u64; 8] {
+  let mut product = [0u64; 8];
+  for i in 0..4 {
+    let mut carry = 0u128;
+    for j in 0..4 {
+      let current = a.0[i] as u128 * b.0[j] as u128 + product[i + j] as u128 + carry;
+      product[i + j] = current as u64;
+      carry = current >> 64;
+    }
+    product[i + 4] = carry as u64;
+  }
+  product
+}
+
+impl Ord for Uint256 {
+  fn cmp(&self, other: &Uint256) -> Ordering {
+    self.0.iter().rev().cmp(other.0.iter().rev())
+  }
+}
+
+impl PartialOrd for Uint256 {
+  fn partial_cmp(&self, other: &Uint256) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl From<u8> for Uint256 {
+  fn from(value: u8) -> Uint256 { Uint256::from(value as u128) }
+}
+
+impl From<u64> for Uint256 {
+  fn from(value: u64) -> Uint256 { Uint256::from(value as u128) }
+}
+
+impl From<u128> for Uint256 {
+  fn from(value: u128) -> Uint256 { Uint256([value as u64, (value >> 64) as u64, 0, 0]) }
+}
+
+impl fmt::Display for Uint256 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // 10^19 is the largest power of ten that fits in a u64
+    const CHUNK: u64 = 10_000_000_000_000_000_000;
+    let mut chunks = vec![];
+    let mut rest = *self;
+    loop {
+      let (quot, rem) = rest.div_rem_u64(CHUNK);
+      chunks.push(rem);
+      if quot == Uint256::ZERO {
+        break;
+      }
+      rest = quot;
+    }
+    let mut chunks = chunks.into_iter().rev();
+    write!(f, "{}", chunks.next().unwrap_or(0))?;
+    chunks.try_for_each(|chunk| write!(f, "{:019}", chunk))
+  }
+}
+
+// decimal digits only, like `u128::from_str` without the optional '+'
+impl FromStr for Uint256 {
+  type Err = ParseUint256Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let err = || ParseUint256Error { input: s.to_string() };
+    if s.is_empty() {
+      return Err(err());
+    }
+    let ten = Uint256::from(10u8);
+    s.bytes().try_fold(Uint256::ZERO, |value, b| {
+      let digit = b.is_ascii_digit().then(|| Uint256::from(b - b'0')).ok_or_else(err)?;
+      value.checked_mul(ten).and_then(|value| value.checked_add(digit)).ok_or_else(err)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MAX: &str = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+
+  fn uint(value: &str) -> Uint256 {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn parse_and_display() {
+    assert_eq!(uint(MAX), Uint256::MAX);
+    assert_eq!(Uint256::MAX.to_string(), MAX);
+    assert_eq!(Uint256::ZERO.to_string(), "0");
+    assert_eq!(Uint256::from(u128::MAX).to_string(), u128::MAX.to_string());
+    assert_eq!(uint("10000000000000000000").to_string(), "10000000000000000000");
+    assert_eq!(uint("340282366920938463463374607431768211455").to_u128(), Some(u128::MAX));
+    assert_eq!(uint("340282366920938463463374607431768211456").to_u128(), None);
+
+    for invalid in ["", "+1", "-1", "1a", "115792089237316195423570985008687907853269984665640564039457584007913129639936"] {
+      assert!(invalid.parse::<Uint256>().is_err(), "{} should not parse", invalid);
+    }
+  }
+
+  #[test]
+  fn checked_arithmetic() {
+    assert_eq!(Uint256::MAX.checked_add(Uint256::from(1u8)), None);
+    assert_eq!(Uint256::from(u128::MAX).checked_add(Uint256::from(1u8)), Some(uint("340282366920938463463374607431768211456")));
+    assert_eq!(Uint256::ZERO.checked_sub(Uint256::from(1u8)), None);
+    assert_eq!(uint("340282366920938463463374607431768211456").checked_sub(Uint256::from(1u8)), Some(Uint256::from(u128::MAX)));
+    assert_eq!(Uint256::from(u128::MAX).checked_mul(Uint256::from(u128::MAX)),
+      Some(uint("115792089237316195423570985008687907852589419931798687112530834793049593217025")));
+    assert_eq!(Uint256::MAX.checked_mul(Uint256::from(2u8)), None);
+    assert!(Uint256::from(u128::MAX) < uint("340282366920938463463374607431768211456"));
+  }
+
+  #[test]
+  fn mul_div_rem_uses_the_full_product() {
+    assert_eq!(Uint256::MAX.mul_div_rem(Uint256::MAX, Uint256::MAX), Some((Uint256::MAX, Uint256::ZERO)));
+    assert_eq!(Uint256::MAX.mul_div_rem(Uint256::from(2u8), Uint256::from(3u8)),
+      Some((uint("77194726158210796949047323339125271902179989777093709359638389338608753093290"), Uint256::ZERO)));
+    assert_eq!(Uint256::from(7u8).mul_div_rem(Uint256::from(5u8), Uint256::from(3u8)), Some((Uint256::from(11u8), Uint256::from(2u8))));
+    assert_eq!(Uint256::MAX.mul_div_rem(Uint256::from(2u8), Uint256::from(1u8)), None);
+    assert_eq!(Uint256::MAX.mul_div_rem(Uint256::from(1u8), Uint256::ZERO), None);
+  }
+}